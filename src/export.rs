@@ -0,0 +1,485 @@
+//! `neon-cli export`: streams the rows of a query or table out as CSV, JSON, NDJSON or Parquet.
+
+use crate::neonutils::{can_decode, reflective_get_opt};
+use parquet::basic::{LogicalType, Repetition, Type as PhysicalType};
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, DataType, DoubleType};
+use parquet::data_type::{FloatType, Int32Type, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedColumnWriter, SerializedFileWriter};
+use parquet::schema::types::Type as SchemaType;
+use postgres::types::{Kind, Type};
+use postgres::{Client, SimpleQueryMessage};
+use serde_json::Value;
+use std::error::Error;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Rows buffered per Parquet row group.  Parquet writes a file column by column, so a group
+/// has to be held in memory before it is written.
+const ROW_GROUP_ROWS: usize = 65_536;
+
+pub const FORMATS: [&str; 4] = ["csv", "json", "ndjson", "parquet"];
+
+pub struct ExportOptions {
+    /// One of FORMATS.
+    pub format: String,
+    pub delimiter: u8,
+    pub quote: csv::QuoteStyle,
+    pub header: bool,
+    /// What a NULL is written as in CSV.
+    pub null: String,
+    /// Rows fetched from the server at a time.
+    pub batch_size: i32,
+}
+
+/// The format implied by a file name's extension, if it has a known one.
+pub fn format_for_path(path: &str) -> Option<&'static str> {
+    let extension = path.rsplit_once('.')?.1.to_lowercase();
+    match extension.as_str() {
+        "csv" => Some("csv"),
+        "json" => Some("json"),
+        "ndjson" | "jsonl" => Some("ndjson"),
+        "parquet" => Some("parquet"),
+        _ => None,
+    }
+}
+
+pub fn quote_style(name: &str) -> csv::QuoteStyle {
+    match name {
+        "always" => csv::QuoteStyle::Always,
+        "non-numeric" => csv::QuoteStyle::NonNumeric,
+        "never" => csv::QuoteStyle::Never,
+        _ => csv::QuoteStyle::Necessary,
+    }
+}
+
+/// A result column: its name and Postgres type.
+struct Column {
+    name: String,
+    ty: Type,
+}
+
+/// The type whose values a column holds, looking through domains.
+fn base_type(ty: &Type) -> &Type {
+    match ty.kind() {
+        Kind::Domain(base) => base_type(base),
+        _ => ty,
+    }
+}
+
+/// A value as JSON: numbers, booleans and json columns keep their type, everything else is
+/// the text reflective_get_opt shows.  numeric stays a string so no precision is lost.
+pub fn json_value(ty: &Type, text: Option<&str>) -> Value {
+    let Some(text) = text else {
+        return Value::Null;
+    };
+    let ty = base_type(ty);
+    let value = match *ty {
+        Type::BOOL => Some(Value::Bool(text == "true" || text == "t")),
+        Type::INT2 | Type::INT4 | Type::INT8 | Type::OID => {
+            text.parse::<i64>().ok().map(Value::from)
+        }
+        Type::FLOAT4 | Type::FLOAT8 => text
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(Value::Number),
+        Type::JSON | Type::JSONB => serde_json::from_str(text).ok(),
+        _ => None,
+    };
+    value.unwrap_or_else(|| Value::String(text.to_string()))
+}
+
+/// One row as a JSON object, keeping the columns in the query's order.
+fn json_object(columns: &[Column], row: &[Option<String>]) -> Result<String, serde_json::Error> {
+    let mut fields = Vec::with_capacity(columns.len());
+    for (column, value) in columns.iter().zip(row) {
+        let value = json_value(&column.ty, value.as_deref());
+        fields.push(format!(
+            "{}:{}",
+            serde_json::to_string(&column.name)?,
+            serde_json::to_string(&value)?
+        ));
+    }
+    Ok(format!("{{{}}}", fields.join(",")))
+}
+
+/// The Parquet type a column is written as.  Types without a direct equivalent are written
+/// as UTF-8 strings of the text reflective_get_opt shows.
+fn parquet_type(column: &Column) -> Result<SchemaType, parquet::errors::ParquetError> {
+    let (physical, logical) = match *base_type(&column.ty) {
+        Type::BOOL => (PhysicalType::BOOLEAN, None),
+        Type::INT2 => (
+            PhysicalType::INT32,
+            Some(LogicalType::Integer {
+                bit_width: 16,
+                is_signed: true,
+            }),
+        ),
+        Type::INT4 => (PhysicalType::INT32, None),
+        Type::INT8 => (PhysicalType::INT64, None),
+        Type::FLOAT4 => (PhysicalType::FLOAT, None),
+        Type::FLOAT8 => (PhysicalType::DOUBLE, None),
+        _ => (PhysicalType::BYTE_ARRAY, Some(LogicalType::String)),
+    };
+    SchemaType::primitive_type_builder(&column.name, physical)
+        .with_repetition(Repetition::OPTIONAL)
+        .with_logical_type(logical)
+        .build()
+}
+
+/// Writes one column of a row group: the non-NULL values and a definition level per row
+/// saying whether it had one.
+fn write_values<T: DataType>(
+    writer: &mut SerializedColumnWriter,
+    cells: &[Option<&str>],
+    convert: impl Fn(&str) -> Option<T::T>,
+) -> Result<(), Box<dyn Error>> {
+    let mut values = Vec::with_capacity(cells.len());
+    let mut levels = Vec::with_capacity(cells.len());
+    for cell in cells {
+        match cell {
+            Some(text) => {
+                let value =
+                    convert(text).ok_or_else(|| format!("can't write {text:?} to Parquet"))?;
+                values.push(value);
+                levels.push(1);
+            }
+            None => levels.push(0),
+        }
+    }
+    writer
+        .typed::<T>()
+        .write_batch(&values, Some(&levels), None)?;
+    Ok(())
+}
+
+struct ParquetSink {
+    writer: SerializedFileWriter<Box<dyn Write + Send>>,
+    types: Vec<Type>,
+    rows: Vec<Vec<Option<String>>>,
+}
+
+impl ParquetSink {
+    fn new(columns: &[Column], out: Box<dyn Write + Send>) -> Result<ParquetSink, Box<dyn Error>> {
+        let fields = columns
+            .iter()
+            .map(|c| parquet_type(c).map(Arc::new))
+            .collect::<Result<Vec<_>, _>>()?;
+        let schema = SchemaType::group_type_builder("schema")
+            .with_fields(fields)
+            .build()?;
+        let properties = Arc::new(WriterProperties::builder().build());
+        Ok(ParquetSink {
+            writer: SerializedFileWriter::new(out, Arc::new(schema), properties)?,
+            types: columns.iter().map(|c| base_type(&c.ty).clone()).collect(),
+            rows: Vec::new(),
+        })
+    }
+
+    fn write_row_group(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut group = self.writer.next_row_group()?;
+        for (i, ty) in self.types.iter().enumerate() {
+            let cells: Vec<Option<&str>> = self.rows.iter().map(|r| r[i].as_deref()).collect();
+            let mut column = group
+                .next_column()?
+                .ok_or("Parquet schema is missing a column")?;
+            match *ty {
+                Type::BOOL => {
+                    write_values::<BoolType>(&mut column, &cells, |s| Some(s == "true" || s == "t"))
+                }
+                Type::INT2 | Type::INT4 => {
+                    write_values::<Int32Type>(&mut column, &cells, |s| s.parse().ok())
+                }
+                Type::INT8 => write_values::<Int64Type>(&mut column, &cells, |s| s.parse().ok()),
+                Type::FLOAT4 => write_values::<FloatType>(&mut column, &cells, |s| s.parse().ok()),
+                Type::FLOAT8 => write_values::<DoubleType>(&mut column, &cells, |s| s.parse().ok()),
+                _ => {
+                    write_values::<ByteArrayType>(&mut column, &cells, |s| Some(ByteArray::from(s)))
+                }
+            }?;
+            column.close()?;
+        }
+        group.close()?;
+        self.rows.clear();
+        Ok(())
+    }
+}
+
+enum Sink {
+    Csv(Box<csv::Writer<Box<dyn Write + Send>>>, String),
+    Json(Box<dyn Write + Send>, Vec<Column>, u64),
+    Ndjson(Box<dyn Write + Send>, Vec<Column>),
+    Parquet(Box<ParquetSink>),
+}
+
+impl Sink {
+    fn new(
+        columns: Vec<Column>,
+        mut out: Box<dyn Write + Send>,
+        options: &ExportOptions,
+    ) -> Result<Sink, Box<dyn Error>> {
+        Ok(match options.format.as_str() {
+            "json" => {
+                out.write_all(b"[")?;
+                Sink::Json(out, columns, 0)
+            }
+            "ndjson" => Sink::Ndjson(out, columns),
+            "parquet" => Sink::Parquet(Box::new(ParquetSink::new(&columns, out)?)),
+            _ => {
+                let mut writer = csv::WriterBuilder::new()
+                    .delimiter(options.delimiter)
+                    .quote_style(options.quote)
+                    .from_writer(out);
+                if options.header {
+                    writer.write_record(columns.iter().map(|c| &c.name))?;
+                }
+                Sink::Csv(Box::new(writer), options.null.clone())
+            }
+        })
+    }
+
+    fn write_row(&mut self, row: Vec<Option<String>>) -> Result<(), Box<dyn Error>> {
+        match self {
+            Sink::Csv(writer, null) => {
+                writer.write_record(row.iter().map(|v| v.as_deref().unwrap_or(null)))?
+            }
+            Sink::Json(out, columns, written) => {
+                let separator = if *written == 0 { "\n" } else { ",\n" };
+                write!(out, "{separator}  {}", json_object(columns, &row)?)?;
+                *written += 1;
+            }
+            Sink::Ndjson(out, columns) => writeln!(out, "{}", json_object(columns, &row)?)?,
+            Sink::Parquet(sink) => {
+                sink.rows.push(row);
+                if sink.rows.len() == ROW_GROUP_ROWS {
+                    sink.write_row_group()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Sink::Csv(mut writer, _) => writer.flush()?,
+            Sink::Json(mut out, _, written) => {
+                out.write_all(if written == 0 { b"]\n" } else { b"\n]\n" })?;
+                out.flush()?;
+            }
+            Sink::Ndjson(mut out, _) => out.flush()?,
+            Sink::Parquet(mut sink) => {
+                if !sink.rows.is_empty() {
+                    sink.write_row_group()?;
+                }
+                sink.writer.close()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs `sql` and writes its rows to `out`, fetching `batch_size` rows at a time from a
+/// portal so the result never has to fit in memory.  Returns how many rows were written.
+pub fn export(
+    client: &mut Client,
+    sql: &str,
+    out: Box<dyn Write + Send>,
+    options: &ExportOptions,
+) -> Result<u64, Box<dyn Error>> {
+    let sql = sql.trim().trim_end_matches(';');
+    let mut transaction = client.transaction()?;
+    let statement = transaction.prepare(sql)?;
+    if statement.columns().is_empty() {
+        return Err("The statement returns no rows to export.".into());
+    }
+    let columns: Vec<Column> = statement
+        .columns()
+        .iter()
+        .map(|c| Column {
+            name: c.name().to_string(),
+            ty: c.type_().clone(),
+        })
+        .collect();
+    let decodable = columns.iter().all(|c| can_decode(&c.ty));
+    let mut sink = Sink::new(columns, out, options)?;
+    let mut rows = 0;
+
+    if decodable {
+        let portal = transaction.bind(&statement, &[])?;
+        loop {
+            let batch = transaction.query_portal(&portal, options.batch_size)?;
+            if batch.is_empty() {
+                break;
+            }
+            for row in &batch {
+                sink.write_row((0..row.len()).map(|i| reflective_get_opt(row, i)).collect())?;
+                rows += 1;
+            }
+        }
+    } else {
+        // Types reflective_get_opt can't decode are fetched as the server's text through a cursor.
+        transaction.batch_execute(&format!(
+            "DECLARE neon_cli_export NO SCROLL CURSOR FOR {sql}"
+        ))?;
+        let fetch = format!("FETCH {} FROM neon_cli_export", options.batch_size);
+        loop {
+            let mut fetched = 0;
+            for message in transaction.simple_query(&fetch)? {
+                if let SimpleQueryMessage::Row(row) = message {
+                    sink.write_row(
+                        (0..row.len())
+                            .map(|i| row.get(i).map(str::to_string))
+                            .collect(),
+                    )?;
+                    fetched += 1;
+                }
+            }
+            if fetched == 0 {
+                break;
+            }
+            rows += fetched;
+        }
+    }
+    sink.finish()?;
+    transaction.commit()?;
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use std::sync::Mutex;
+
+    /// A writer tests can read back after the sink that owns it is finished.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn columns() -> Vec<Column> {
+        let column = |name: &str, ty: Type| Column {
+            name: name.to_string(),
+            ty,
+        };
+        vec![
+            column("id", Type::INT4),
+            column("name", Type::TEXT),
+            column("ok", Type::BOOL),
+            column("doc", Type::JSONB),
+        ]
+    }
+
+    fn rows() -> Vec<Vec<Option<String>>> {
+        let cell = |s: &str| Some(s.to_string());
+        vec![
+            vec![
+                cell("1"),
+                cell("a, \"b\""),
+                cell("true"),
+                cell(r#"{"k": [1]}"#),
+            ],
+            vec![cell("2"), None, cell("f"), None],
+        ]
+    }
+
+    fn options(format: &str) -> ExportOptions {
+        ExportOptions {
+            format: format.to_string(),
+            delimiter: b',',
+            quote: csv::QuoteStyle::Necessary,
+            header: true,
+            null: String::new(),
+            batch_size: 100,
+        }
+    }
+
+    fn render(options: &ExportOptions) -> Vec<u8> {
+        let out = Shared::default();
+        let mut sink = Sink::new(columns(), Box::new(out.clone()), options).unwrap();
+        for row in rows() {
+            sink.write_row(row).unwrap();
+        }
+        sink.finish().unwrap();
+        let bytes = out.0.lock().unwrap().clone();
+        bytes
+    }
+
+    #[test]
+    fn csv_quotes_and_writes_nulls() {
+        let csv = String::from_utf8(render(&options("csv"))).unwrap();
+        assert_eq!(
+            csv,
+            "id,name,ok,doc\n1,\"a, \"\"b\"\"\",true,\"{\"\"k\"\": [1]}\"\n2,,f,\n"
+        );
+
+        let mut tsv = options("csv");
+        tsv.delimiter = b'\t';
+        tsv.header = false;
+        tsv.null = "NULL".to_string();
+        let tsv = String::from_utf8(render(&tsv)).unwrap();
+        assert!(tsv.ends_with("2\tNULL\tf\tNULL\n"), "{tsv}");
+    }
+
+    #[test]
+    fn json_keeps_column_order_and_types() {
+        let ndjson = String::from_utf8(render(&options("ndjson"))).unwrap();
+        assert_eq!(
+            ndjson,
+            "{\"id\":1,\"name\":\"a, \\\"b\\\"\",\"ok\":true,\"doc\":{\"k\":[1]}}\n\
+             {\"id\":2,\"name\":null,\"ok\":false,\"doc\":null}\n"
+        );
+
+        let json: Value = serde_json::from_slice(&render(&options("json"))).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 2);
+        assert_eq!(json[1]["ok"], Value::Bool(false));
+    }
+
+    #[test]
+    fn json_numbers_that_json_cannot_hold_stay_strings() {
+        assert_eq!(json_value(&Type::FLOAT8, Some("NaN")), Value::from("NaN"));
+        assert_eq!(json_value(&Type::FLOAT4, Some("1.5")), Value::from(1.5));
+        assert_eq!(
+            json_value(&Type::NUMERIC, Some("12345678901234567890.1")),
+            Value::from("12345678901234567890.1")
+        );
+    }
+
+    #[test]
+    fn parquet_round_trips() {
+        let bytes = render(&options("parquet"));
+        let path =
+            std::env::temp_dir().join(format!("neon-cli-export-{}.parquet", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let rows: Vec<String> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|r| r.unwrap().to_string())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                r#"{id: 1, name: "a, "b"", ok: true, doc: "{"k": [1]}"}"#,
+                r#"{id: 2, name: null, ok: false, doc: null}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn formats_follow_the_file_extension() {
+        assert_eq!(format_for_path("out/users.PARQUET"), Some("parquet"));
+        assert_eq!(format_for_path("users.jsonl"), Some("ndjson"));
+        assert_eq!(format_for_path("users"), None);
+    }
+}
@@ -0,0 +1,124 @@
+//! `--query`: a jq expression applied to API responses before they are printed, so scripts
+//! can pull out an id or connection string without piping through jq.
+
+use jaq_core::load::{Arena, File, Loader};
+use jaq_core::{Compiler, Ctx, Native, RcIter};
+use jaq_json::Val;
+use serde_json::Value;
+
+type Filter = jaq_core::Filter<Native<Val>>;
+
+/// JMESPath-style paths such as `branch.id` or `projects[*].id` as the jq they mean, so
+/// both spellings work.  Anything that isn't a bare path is taken to be jq already.
+fn to_jq(expression: &str) -> String {
+    let expression = expression.trim();
+    let bare_path = expression.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && expression
+            .chars()
+            .all(|c| c.is_alphanumeric() || "_.[]*".contains(c));
+    if bare_path {
+        format!(".{}", expression.replace("[*]", "[]"))
+    } else {
+        expression.to_string()
+    }
+}
+
+fn compile(expression: &str) -> Result<Filter, String> {
+    let code = to_jq(expression);
+    let program = File {
+        code: code.as_str(),
+        path: (),
+    };
+    let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs()));
+    let arena = Arena::default();
+    let invalid = |detail: String| format!("invalid query {expression:?}: {detail}");
+    let modules = loader.load(&arena, program).map_err(|errors| {
+        // Where parsing stopped is the rest of the expression from that point.
+        let describe = |expected: &str, rest: &str| match rest {
+            "" => format!("expected {expected} at the end"),
+            rest => format!("expected {expected} at {rest:?}"),
+        };
+        let detail = match errors.into_iter().next().map(|(_, e)| e) {
+            Some(jaq_core::load::Error::Lex(e)) => {
+                e.first().map(|(expect, at)| describe(expect.as_str(), at))
+            }
+            Some(jaq_core::load::Error::Parse(e)) => {
+                e.first().map(|(expect, at)| describe(expect.as_str(), at))
+            }
+            _ => None,
+        };
+        invalid(detail.unwrap_or("can't parse it".to_string()))
+    })?;
+    Compiler::default()
+        .with_funs(jaq_std::funs().chain(jaq_json::funs()))
+        .compile(modules)
+        .map_err(|errors| {
+            let names: Vec<&str> = errors
+                .iter()
+                .flat_map(|(_, e)| e.iter().map(|(name, _)| *name))
+                .collect();
+            invalid(format!("{} is not defined", names.join(", ")))
+        })
+}
+
+/// Checks that `expression` compiles, for use as a clap value parser, so a typo is reported
+/// before any request is sent.
+pub fn parse(expression: &str) -> Result<String, String> {
+    compile(expression).map(|_| expression.to_string())
+}
+
+/// Runs `expression` on `input`, returning every value it produces.
+pub fn apply(expression: &str, input: Value) -> Result<Vec<Value>, String> {
+    let filter = compile(expression)?;
+    let inputs = RcIter::new(core::iter::empty());
+    filter
+        .run((Ctx::new([], &inputs), Val::from(input)))
+        .map(|output| {
+            output
+                .map(Value::from)
+                .map_err(|e| format!("query {expression:?} failed: {e}"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn response() -> Value {
+        json!({
+            "branch": {"id": "br-1", "name": "dev"},
+            "projects": [{"id": "p-1", "pg_version": 15}, {"id": "p-2", "pg_version": 16}],
+        })
+    }
+
+    #[test]
+    fn jmespath_paths_read_as_jq() {
+        assert_eq!(to_jq("branch.id"), ".branch.id");
+        assert_eq!(to_jq("projects[*].id"), ".projects[].id");
+        assert_eq!(to_jq(".projects | length"), ".projects | length");
+    }
+
+    #[test]
+    fn queries_pick_values_out_of_responses() {
+        assert_eq!(apply("branch.id", response()).unwrap(), vec![json!("br-1")]);
+        assert_eq!(
+            apply("projects[*].id", response()).unwrap(),
+            vec![json!("p-1"), json!("p-2")]
+        );
+        let newest = "[.projects[] | select(.pg_version > 15) | .id]";
+        assert_eq!(apply(newest, response()).unwrap(), vec![json!(["p-2"])]);
+    }
+
+    #[test]
+    fn bad_queries_are_reported() {
+        assert!(parse(".branch.").is_err());
+        assert!(parse("nosuchfunction(1)")
+            .unwrap_err()
+            .contains("nosuchfunction"));
+        assert!(apply(".branch.id.x", response())
+            .unwrap_err()
+            .contains("failed"));
+    }
+}
@@ -3,28 +3,32 @@
 //#![allow(unused_variables)]
 
 use clap::Parser;
-use comfy_table::*;
-use comfy_table::{modifiers::UTF8_ROUND_CORNERS, presets::UTF8_FULL};
 use core::panic;
-use dotenv::dotenv;
-use futures::executor::block_on;
 use openssl::ssl::{SslConnector, SslMethod};
-use postgres::{types::ToSql, Client};
+use postgres::Client;
 use postgres_openssl::MakeTlsConnector;
 use serde::Deserialize;
-use serde_json::{json, to_string_pretty, Value};
-use std::{collections::HashMap, error::Error, vec::Vec};
+use serde_json::{json, to_string_pretty, Map, Value};
+use std::{error::Error, io::Write, time::Duration, vec::Vec};
+mod config;
+mod export;
+mod jsonquery;
+mod models;
 mod neonutils;
 mod networking;
+mod output;
+mod shell;
 use csv::StringRecord;
 
-use crate::neonutils::{print_generic_json_table, reflective_get};
+use crate::config::{config_file_path, load_session, ConfigFile, Profile, SessionOverrides};
+use crate::export::{json_value, ExportOptions};
+use crate::models::{
+    ConnectionUri, Endpoint, EndpointChanges, EndpointResponse, Listing, NewBranch, ProjectChanges,
+    PROJECT_QUOTAS,
+};
+use crate::neonutils::{can_decode, query_as_text, quote_array_element, reflective_get_opt};
 use crate::networking::*;
-
-#[macro_use]
-extern crate dotenv_codegen;
-
-const NEON_BASE_URL: &str = "https://console.neon.tech/api/v2";
+use crate::output::{Filter, Rows, SortKey, View};
 
 #[derive(Parser)]
 #[command(author = "Tim Tully. <tim@menlovc.com>")]
@@ -34,22 +38,122 @@ struct Cli {
     action: Action,
     #[arg(short, long, action = clap::ArgAction::Count)]
     debug: u8,
+    #[arg(long, global = true, help = String::from("Named profile from the config file to use. Overrides NEON_PROFILE and current_profile."))]
+    profile: Option<String>,
+    #[arg(long, global = true, help = String::from("Neon API key. Overrides NEON_API_KEY, .env and the config file."))]
+    api_key: Option<String>,
+    #[arg(long, global = true, help = String::from("Base URL of the Neon API. Overrides NEON_API_URL, .env and the config file."))]
+    api_url: Option<String>,
+    #[arg(long, global = true, help = String::from("Postgres connection string. Overrides DATABASE_URL, .env and the config file."))]
+    connect_string: Option<String>,
+    #[arg(long, global = true, help = String::from("Database name. Overrides PGDATABASE, .env and the config file, and replaces the database in a connect string from any of them. For the branch command's database actions, the database to work on."))]
+    database: Option<String>,
+    #[arg(long, global = true, default_value_t = 3, help = String::from("How many times to retry API requests that fail with 423, 429 or 5xx."))]
+    max_retries: u32,
+    #[arg(long, global = true, help = String::from("Also retry POST and PATCH requests, which may not be safe to repeat."))]
+    retry_writes: bool,
+    #[arg(long, global = true, help = String::from("Wait for the operations started by a command to finish before exiting."))]
+    wait: bool,
+    #[arg(long, global = true, default_value_t = 300, help = String::from("How many seconds --wait waits for operations before giving up."))]
+    timeout: u64,
+    #[arg(long, global = true, value_parser = jsonquery::parse, help = String::from("jq expression (or a JMESPath-style path like branch.id) applied to API responses before printing."))]
+    query: Option<String>,
+}
+const API_FORMAT_HELP: &str = r#"Output format. "json" and "yaml" print the whole response; "table", "csv", "tsv", "ndjson", "markdown" and "vertical" print its items, one row each; "raw" prints strings unquoted, one per line, for use with --query."#;
+
+/// Options for which items the row formats show, shared by the API commands.
+#[derive(clap::Args, Debug)]
+struct ViewArgs {
+    #[arg(long, value_delimiter = ',', help = String::from("Fields to show, as dotted paths into nested objects, e.g. id,name,settings.quota.active_time_seconds."))]
+    columns: Vec<String>,
+    #[arg(long, value_parser = SortKey::parse, help = String::from("Sort items by a field, e.g. created_at or created_at:desc."))]
+    sort_by: Option<SortKey>,
+    #[arg(long, value_parser = Filter::parse, help = String::from("Only show items where a field has a value, e.g. current_state=idle or type!=read_only. Repeat to combine."))]
+    filter: Vec<Filter>,
+    #[arg(long, default_value_t = output::DEFAULT_MAX_DEPTH, help = String::from("Levels of nested objects to show as dotted columns, e.g. settings.pg_settings.max_connections, in the row formats other than ndjson. Deeper objects are shown as JSON; 0 shows every nested value as JSON."))]
+    max_depth: usize,
+}
+
+impl From<ViewArgs> for View {
+    fn from(args: ViewArgs) -> View {
+        View {
+            columns: args.columns,
+            sort_by: args.sort_by,
+            filters: args.filter,
+            max_depth: args.max_depth,
+        }
+    }
 }
+
+/// Options for the paginated lists: list-projects, list-operations and consumption.
+#[derive(clap::Args, Debug)]
+struct PageArgs {
+    #[arg(long, help = String::from("Fetch every page of the list, following the API's pagination cursor, and print the items together. With -f ndjson they are printed a page at a time."))]
+    all: bool,
+    #[arg(short = 'l', long, alias = "limit", value_parser = clap::value_parser!(u32).range(1..), help = String::from("How many items to ask for per page."))]
+    page_size: Option<u32>,
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), help = String::from("Stop after this many items."))]
+    max_items: Option<u64>,
+    #[arg(short, long, help = String::from("Start after this cursor, the pagination.cursor of an earlier response."))]
+    cursor: Option<String>,
+}
+
+impl From<PageArgs> for Paging {
+    fn from(args: PageArgs) -> Paging {
+        Paging {
+            first: Page {
+                cursor: args.cursor,
+                limit: args.page_size,
+            },
+            all: args.all,
+            max_items: args.max_items.map(|n| n as usize),
+        }
+    }
+}
+
 #[derive(clap::Subcommand, Debug)]
 enum Action {
     #[clap(about = "Execute a query")]
     Query {
         #[arg(short, long, help = String::from("SQL query string to execute."))]
         sql: String,
+        #[arg(short, long, default_value_t = String::from("table"), value_parser = output::FORMATS, help = String::from("Output format for the rows. vertical prints each row as its own table, like psql's \\x."))]
+        format: String,
     },
+    #[clap(about = "Interactive SQL shell on a single connection.")]
+    Shell {},
     #[clap(about = "Get information about projects in Neon.")]
     Projects {
-        #[arg(short, long, help = String::from(r#"Format output for the projects. Can be one of "list-projects", "project-details", "delete-project""#))]
+        #[arg(short, long, help = String::from(r#"Format output for the projects. Can be one of "list-projects", "project-details", "create-project", "update-project", "delete-project""#))]
         action: String,
-        #[arg(short, long, help = String::from(r#"The project identifier to use in the operation, if any. list-projects does not use this arg."#))]
+        #[arg(short, long, help = String::from(r#"The project identifier to use in the operation, if any. list-projects and create-project do not use this arg."#))]
         project: Option<String>,
-        #[arg(short, long, default_value_t = String::from("json"), help = String::from(r#"Format output for the projects. Can be one of "json" or "table""#))]
+        #[arg(short, long, default_value_t = String::from("json"), value_parser = output::API_FORMATS, help = String::from(API_FORMAT_HELP))]
         format: String,
+        #[command(flatten)]
+        view: ViewArgs,
+        #[command(flatten)]
+        page: PageArgs,
+        #[arg(long, help = String::from("create-project, update-project: name for the project."))]
+        name: Option<String>,
+        #[arg(long, help = String::from("create-project: region to create the project in, e.g. aws-us-east-2."))]
+        region: Option<String>,
+        #[arg(long, value_parser = clap::value_parser!(u32).range(14..), help = String::from("create-project: Postgres major version, e.g. 16."))]
+        pg_version: Option<u32>,
+        #[arg(long, help = String::from("create-project: name for the project's default branch."))]
+        default_branch: Option<String>,
+        #[arg(long, value_parser = parse_cu, help = String::from("create-project, update-project: autoscaling minimum for new endpoints, in compute units."))]
+        min_cu: Option<f64>,
+        #[arg(long, value_parser = parse_cu, help = String::from("create-project, update-project: autoscaling maximum for new endpoints, in compute units."))]
+        max_cu: Option<f64>,
+        #[arg(long, help = String::from("create-project, update-project: seconds of history kept for point-in-time branching."))]
+        history_retention: Option<u32>,
+        #[arg(long, value_delimiter = ',', help = String::from(r#"create-project, update-project: IP addresses or ranges allowed to connect, e.g. 192.0.2.1,198.51.100.0/24. "" allows any."#))]
+        allowed_ips: Option<Vec<String>>,
+        #[arg(long = "quota", value_parser = parse_quota, help = String::from("create-project, update-project: a limit such as active_time_seconds=3600. Repeat for several. One of active_time_seconds, compute_time_seconds, written_data_bytes, data_transfer_bytes or logical_size_bytes."))]
+        quotas: Vec<(String, u64)>,
+        #[arg(long, help = String::from("create-project, update-project: turn on logical replication. It can't be turned off again."))]
+        logical_replication: bool,
     },
     #[clap(about = "Get information about keys in Neon.")]
     Keys {
@@ -57,25 +161,45 @@ enum Action {
         action: String,
         #[arg(short, long, help = String::from("Project the key belongs to."))]
         name: Option<String>,
-        #[arg(short, long, default_value_t = String::from("json"), help = String::from(r#"Format output for the keys. Can be one of "json" or "table""#))]
+        #[arg(short, long, default_value_t = String::from("json"), value_parser = output::API_FORMATS, help = String::from(API_FORMAT_HELP))]
         format: String,
+        #[command(flatten)]
+        view: ViewArgs,
     },
     #[clap(about = "Get information about branches in Neon.")]
     Branch {
-        #[arg(short, long, help = String::from(r#"Branch action to be performed. Can be one of "list-branches"."#))]
+        #[arg(short, long, help = String::from(r#"Branch action to be performed. Can be one of "list-branches", "branch-details", "create-branch", "delete-branch", "list-endpoints", "list-roles", "role-details", "role-create", "role-delete", "role-reset-password", "role-reveal-password", "connection-uri", "list-databases", "database-details", "create-database", "update-database" or "delete-database". The database actions work on the database named with --database."#))]
         action: String,
         #[arg(short, long, help = String::from("Project the branch belongs to."))]
         project: Option<String>,
         #[arg(short, long, help = String::from("Branch to get data for."))]
         branch: Option<String>,
-        #[arg(short, long, default_value_t = String::from("json"), help = String::from(r#"Format output for the keys. Can be one of "json" or "table""#))]
+        #[arg(short, long, default_value_t = String::from("json"), value_parser = output::API_FORMATS, help = String::from(API_FORMAT_HELP))]
         format: String,
+        #[command(flatten)]
+        view: ViewArgs,
         #[clap(short, long)]
         roles: Option<String>,
+        #[arg(long, help = String::from("create-database, update-database: the role that owns the database."))]
+        owner: Option<String>,
+        #[arg(short, long, help = String::from("connection-uri: the endpoint to connect through. Defaults to the branch's read-write endpoint."))]
+        endpoint: Option<String>,
+        #[arg(long, help = String::from("connection-uri: connect through the endpoint's connection pooler."))]
+        pooled: bool,
+        #[arg(long, help = String::from("create-branch: the parent branch id. Defaults to the project's primary branch."))]
+        parent: Option<String>,
+        #[arg(long, conflicts_with = "parent_lsn", value_parser = parse_timestamp, help = String::from("create-branch: branch from the parent's state at this RFC 3339 time, e.g. 2023-05-17T18:00:00Z."))]
+        parent_timestamp: Option<String>,
+        #[arg(long, help = String::from("create-branch: branch from the parent's state at this LSN, e.g. 0/1FA5A60."))]
+        parent_lsn: Option<String>,
+        #[arg(long, help = String::from("create-branch: name for the new branch. update-database: new name for the database."))]
+        name: Option<String>,
+        #[arg(long, help = String::from("create-branch: also create a read-write endpoint for the new branch."))]
+        with_endpoint: bool,
     },
     #[clap(about = "Get information about endpoints in Neon.")]
     Endpoints {
-        #[arg(short, long, help = String::from(r#"Endpoint action to be performed. Can be one of "start", "suspend", "list" or "details"."#))]
+        #[arg(short, long, help = String::from(r#"Endpoint action to be performed. Can be one of "create", "start", "suspend", "restart", "update", "list", "details" or "delete"."#))]
         action: String,
         #[arg(short, long, help = String::from("Project the endpoint belongs to."))]
         project: Option<String>,
@@ -83,8 +207,30 @@ enum Action {
         branch: Option<String>,
         #[arg(short, long, help = String::from("Branch to get data for."))]
         endpoint: Option<String>,
-        #[arg(short, long, help = String::from("Config for endpoint create (json object as a string). See https://api-docs.neon.tech/reference/createprojectendpoint"))]
-        initconfig: Option<String>,
+        #[arg(short, long, value_parser = parse_initconfig, help = String::from("create: settings for the new endpoint as a JSON object, or @file.json to read them from a file. The other create options override what it sets. See https://api-docs.neon.tech/reference/createprojectendpoint"))]
+        initconfig: Option<Map<String, Value>>,
+        #[arg(long = "type", value_parser = ["read_write", "read_only"], help = String::from("create: whether the endpoint can write, or only read."))]
+        endpoint_type: Option<String>,
+        #[arg(long, help = String::from("create: region for the endpoint, e.g. aws-us-east-2. Defaults to the project's."))]
+        region: Option<String>,
+        #[arg(long, value_parser = parse_cu, help = String::from("create, update: autoscaling minimum, in compute units."))]
+        min_cu: Option<f64>,
+        #[arg(long, value_parser = parse_cu, help = String::from("create, update: autoscaling maximum, in compute units."))]
+        max_cu: Option<f64>,
+        #[arg(long, allow_negative_numbers = true, value_parser = clap::value_parser!(i64).range(-1..=604800), help = String::from("create, update: seconds of inactivity before the endpoint is suspended. 0 uses Neon's default and -1 never suspends it."))]
+        suspend_timeout: Option<i64>,
+        #[arg(long, help = String::from("create, update: whether connections can go through the connection pooler (true or false)."))]
+        pooler_enabled: Option<bool>,
+        #[arg(long, value_parser = ["transaction", "session"], help = String::from("create, update: the connection pooler's mode."))]
+        pooler_mode: Option<String>,
+        #[arg(long, help = String::from("create, update: true stops connections to the endpoint, false allows them again."))]
+        disabled: Option<bool>,
+        #[arg(long, help = String::from("create, update: whether passwordless connections are allowed (true or false)."))]
+        passwordless_access: Option<bool>,
+        #[arg(short, long, default_value_t = String::from("json"), value_parser = output::API_FORMATS, help = String::from(API_FORMAT_HELP))]
+        format: String,
+        #[command(flatten)]
+        view: ViewArgs,
     },
     #[clap(about = "Get information about operations in Neon.")]
     Operations {
@@ -94,96 +240,237 @@ enum Action {
         project: Option<String>,
         #[arg(short, long, help = String::from("Identifier for an operation to get data for."))]
         operation: Option<String>,
-        #[arg(short, long, default_value_t = String::from("json"), help = String::from(r#"Format output for the keys. Can be one of "json" or "table""#))]
+        #[arg(short, long, default_value_t = String::from("json"), value_parser = output::API_FORMATS, help = String::from(API_FORMAT_HELP))]
         format: String,
+        #[command(flatten)]
+        view: ViewArgs,
+        #[command(flatten)]
+        page: PageArgs,
     },
     #[clap(about = "Get information about consumption in Neon.")]
     Consumption {
-        #[arg(short, long, help = String::from("Pagination limit for the report."))]
-        limit: Option<u32>,
-        #[arg(short, long, help = String::from("Cursor value used for next page in pagination."))]
-        cursor: Option<String>,
+        #[command(flatten)]
+        page: PageArgs,
+        #[arg(short, long, default_value_t = String::from("json"), value_parser = output::API_FORMATS, help = String::from(API_FORMAT_HELP))]
+        format: String,
+        #[command(flatten)]
+        view: ViewArgs,
     },
-    #[clap(about = "Import data from csv file (TEXT only for now).")]
+    #[clap(about = "Import data from a csv file.")]
     Import {
-        #[arg(short, long, help = String::from("The table to load data into."))]
+        #[arg(short, long, help = String::from("The table to load data into, as name or schema.name. Names are matched exactly, so give them in the case they were created with."))]
         table: String,
         #[arg(short, long, help = String::from("The CSV file from while to load data. Ensure you have a header row at the top."))]
         file: String,
-        #[arg(short, long, help = String::from("Delimiter used in the row.  Default is ','."))]
-        delimiter: Option<String>,
+        #[arg(short, long, value_parser = parse_delimiter, help = String::from("Delimiter used in the row.  Default is ','."))]
+        delimiter: Option<u8>,
+        #[arg(short, long, value_delimiter = ',', help = String::from("Table columns the CSV fields go into, in order (e.g. a,b,c). Defaults to the header row's names."))]
+        columns: Vec<String>,
+        #[arg(long = "null", default_values_t = [String::new()], help = String::from("A field value that means NULL. Repeat for several, e.g. --null '' --null NULL. Default is an empty field."))]
+        nulls: Vec<String>,
+        #[arg(long, default_value_t = String::from("abort"), value_parser = ["abort", "report"], help = String::from(r#"What to do with a row that doesn't fit the table: "abort" loads nothing, "report" prints it and loads the other rows. Only the client-side type checks are skipped this way; a value Postgres itself rejects still loads nothing."#))]
+        on_error: String,
+    },
+    #[clap(about = "Export rows from a query or table to a csv, json, ndjson or parquet file.")]
+    Export {
+        #[arg(short, long, required_unless_present = "table", conflicts_with = "table", help = String::from("SQL query whose rows to export."))]
+        sql: Option<String>,
+        #[arg(short, long, help = String::from("Table to export, instead of --sql, as name or schema.name. Names are matched exactly, so give them in the case they were created with."))]
+        table: Option<String>,
+        #[arg(short, long, help = String::from("File to write. Default is stdout."))]
+        output: Option<String>,
+        #[arg(short, long, value_parser = export::FORMATS, help = String::from("Output format. Default is taken from the --output file's extension, or csv."))]
+        format: Option<String>,
+        #[arg(short, long, default_value = ",", value_parser = parse_delimiter, help = String::from("csv: delimiter between fields."))]
+        delimiter: u8,
+        #[arg(long, default_value_t = String::from("necessary"), value_parser = ["necessary", "always", "non-numeric", "never"], help = String::from("csv: which fields to quote."))]
+        quote: String,
+        #[arg(long, help = String::from("csv: leave out the header row."))]
+        no_header: bool,
+        #[arg(long, default_value_t = String::new(), help = String::from("csv: what to write for NULL. Default is an empty field."))]
+        null: String,
+        #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(i32).range(1..), help = String::from("Rows to fetch from the server at a time."))]
+        batch_size: i32,
+    },
+    #[clap(about = "Manage named connection profiles in the config file.")]
+    Profile {
+        #[arg(short, long, help = String::from(r#"Profile action to take. Can be one of "list", "add", "remove" or "use". "add" also saves --api-key, --api-url, --connect-string and --database."#))]
+        action: String,
+        #[arg(short, long, help = String::from("Name of the profile."))]
+        name: Option<String>,
+        #[arg(long, help = String::from("Environment variable holding the API key for the profile."))]
+        api_key_env: Option<String>,
+        #[arg(short, long, help = String::from("Default project id for the profile."))]
+        project: Option<String>,
+        #[arg(short, long, help = String::from("Default branch id for the profile."))]
+        branch: Option<String>,
     },
 }
 
+/// Compute units come in quarters and halves below one, and whole numbers above.
+fn parse_cu(s: &str) -> Result<f64, String> {
+    let cu: f64 = s
+        .parse()
+        .map_err(|_| format!("expected a number of compute units, found {s}"))?;
+    if cu == 0.25 || cu == 0.5 || (cu.fract() == 0.0 && (1.0..=56.0).contains(&cu)) {
+        Ok(cu)
+    } else {
+        Err("expected 0.25, 0.5 or a whole number of compute units from 1 to 56".to_string())
+    }
+}
+
+/// `--quota name=limit`, e.g. active_time_seconds=3600.
+fn parse_quota(s: &str) -> Result<(String, u64), String> {
+    let (name, limit) = s.split_once('=').ok_or("expected name=limit")?;
+    if !PROJECT_QUOTAS.contains(&name) {
+        return Err(format!(
+            "expected one of {}, found {name}",
+            PROJECT_QUOTAS.join(", ")
+        ));
+    }
+    let limit = limit
+        .parse()
+        .map_err(|_| format!("expected a whole number for {name}, found {limit}"))?;
+    Ok((name.to_string(), limit))
+}
+
+/// A CSV delimiter is a single byte, so only one ASCII character will do.
+fn parse_delimiter(s: &str) -> Result<u8, String> {
+    match s.as_bytes() {
+        [b] if b.is_ascii() => Ok(*b),
+        _ => Err(format!("expected a single ASCII character, found {s:?}")),
+    }
+}
+
+fn parse_timestamp(s: &str) -> Result<String, String> {
+    chrono::DateTime::parse_from_rfc3339(s)
+        .map(|_| s.to_string())
+        .map_err(|e| format!("expected an RFC 3339 timestamp like 2023-05-17T18:00:00Z ({e})"))
+}
+
 #[derive(Deserialize, Debug)]
 pub struct NeonSession {
     database: String,
     neon_api_key: String,
     connect_string: String,
+    project: String,
+    branch: String,
+    api_url: String,
+    #[serde(skip)]
+    retry: RetryPolicy,
+    /// How long to wait for operations to finish; `None` returns as soon as they are started.
+    #[serde(skip)]
+    wait: Option<Duration>,
+    /// Which items and fields API commands print in the row formats.
+    #[serde(skip)]
+    view: View,
+    /// `--query`: the jq expression API responses are run through before printing.
+    #[serde(skip)]
+    query: Option<String>,
+    /// How much of a paginated list the list commands fetch.
+    #[serde(skip)]
+    paging: Paging,
 }
 
 impl NeonSession {
     fn new(
-        connect_string: &String,
-        user: &String,
-        password: &String,
-        hostname: &String,
-        database: &String,
-        neon_api_key: &String,
+        connect_string: &str,
+        user: &str,
+        password: &str,
+        hostname: &str,
+        port: &str,
+        database: &str,
+        neon_api_key: &str,
     ) -> NeonSession {
         let mut final_connect: String = String::from(connect_string);
         if final_connect.is_empty() {
-            final_connect = format!("postgres://{user}:{password}@{hostname}:5432/{database}");
+            final_connect = format!(
+                "postgres://{}:{}@{hostname}:{port}/{database}",
+                percent_encode(user),
+                percent_encode(password)
+            );
         }
         NeonSession {
-            database: database.clone(),
-            neon_api_key: neon_api_key.clone(),
+            database: database.to_string(),
+            neon_api_key: neon_api_key.to_string(),
             connect_string: final_connect,
+            project: String::new(),
+            branch: String::new(),
+            api_url: String::new(),
+            retry: RetryPolicy::default(),
+            wait: None,
+            view: View::default(),
+            query: None,
+            paging: Paging::default(),
         }
     }
 
     fn connect(&self) -> Result<postgres::Client, Box<dyn std::error::Error>> {
         let builder = SslConnector::builder(SslMethod::tls())?;
         let connector = MakeTlsConnector::new(builder.build());
-        let uri = format!("{}", self.connect_string);
-        println!("uri is {uri}");
-        let client = Client::connect(&uri, connector)?;
+        let client =
+            Client::connect(&self.connect_string, connector).map_err(|e| pg_error_message(&e))?;
         Ok(client)
     }
 }
 
+/// postgres::Error displays as just "db error" or "error connecting to server"; the
+/// server's own message, or the underlying cause, is what's useful.
+fn pg_error_message(e: &postgres::Error) -> String {
+    match (e.as_db_error(), Error::source(e)) {
+        (Some(db), _) => format!("{}: {}", db.severity(), db.message()),
+        (None, Some(cause)) => format!("{e}: {cause}"),
+        (None, None) => e.to_string(),
+    }
+}
+
 struct Query {
     query: String,
+    /// How to print the result; one of output::FORMATS.
+    format: String,
 }
 
 impl Query {
     //https://github.com/sfackler/rust-postgres/issues/858
-    fn query(&self, mut client: postgres::Client) {
-        println!("Executing query: {}", self.query);
-        let mut saw_first_row = false;
-        let res = client.query(&self.query, &[]).unwrap();
-        let mut num_cols: u64 = 0;
-        let mut table = Table::new();
-        table
-            .load_preset(UTF8_FULL)
-            .apply_modifier(UTF8_ROUND_CORNERS)
-            .set_content_arrangement(ContentArrangement::Dynamic)
-            .set_header(vec!["Header"]);
-
-        for row in &res {
-            if !saw_first_row {
-                let col_names: Vec<String> =
-                    row.columns().iter().map(|c| c.name().to_string()).collect();
-                num_cols = col_names.len() as u64;
-                table.set_header(col_names);
-                saw_first_row = true;
-            }
-            let row_strs: Vec<String> = (0..num_cols)
-                .map(|i: u64| reflective_get(row, i as usize))
-                .collect();
-            table.add_row(row_strs);
+    fn query(&self, client: &mut postgres::Client) -> Result<(), String> {
+        let pg = |e: postgres::Error| pg_error_message(&e);
+        let statement = client.prepare(&self.query).map_err(pg)?;
+        if statement.columns().is_empty() {
+            let affected = client.execute(&statement, &[]).map_err(pg)?;
+            println!("OK, {affected} rows affected.");
+            return Ok(());
         }
-        println!("{table}");
+        let columns = statement.columns();
+        let decodable = columns.iter().all(|c| can_decode(c.type_()));
+        let text: Vec<Vec<Option<String>>> = if decodable {
+            client
+                .query(&statement, &[])
+                .map_err(pg)?
+                .iter()
+                .map(|row| {
+                    (0..columns.len())
+                        .map(|i| reflective_get_opt(row, i))
+                        .collect()
+                })
+                .collect()
+        } else {
+            query_as_text(client, &self.query).map_err(pg)?
+        };
+
+        let rows = Rows {
+            columns: columns.iter().map(|c| c.name().to_string()).collect(),
+            rows: text
+                .iter()
+                .map(|row| {
+                    let typed = columns.iter().zip(row);
+                    typed
+                        .map(|(c, v)| json_value(c.type_(), v.as_deref()))
+                        .collect()
+                })
+                .collect(),
+        };
+        output::print(&rows, &self.format);
+        Ok(())
     }
 }
 
@@ -191,415 +478,1150 @@ impl Drop for Query {
     fn drop(&mut self) {}
 }
 
-fn initialize_env() -> NeonSession {
-    let config = NeonSession::new(
-        &dotenv!("CONNECT_STRING").to_string(),
-        &dotenv!("USER").to_string(),
-        &dotenv!("PASSWORD").to_string(),
-        &dotenv!("HOSTNAME").to_string(),
-        &dotenv!("DATABASE").to_string(),
-        &dotenv!("NEON_API_KEY").to_string(),
-    );
-    return config;
-}
-
-fn build_uri(endpoint: String) -> String {
-    format!("{}{endpoint}", NEON_BASE_URL.to_string())
-}
-
-// String in the Result is unformatted JSON (non-pretty).
-fn handle_http_result(r: Result<String, Box<dyn Error>>) -> serde_json::Result<()> {
-    match r {
-        Ok(s) => {
-            let json_blob: Value = serde_json::from_str(&s)?;
-            let formatted: Result<String, serde_json::Error> = to_string_pretty(&json_blob);
-            println!("{}", formatted.unwrap());
-        }
-        Err(e) => {
-            panic!("Error: {e}");
-        }
-    }
-    Ok(())
-}
-
 #[tokio::main]
 async fn perform_keys_action(
-    action: &String,
-    name: &String,
-    format: &String,
+    action: &str,
+    name: &str,
+    format: &str,
     neon_config: &NeonSession,
-) {
-    let r: Result<String, Box<dyn Error>>;
-    match action {
-        s if s == "list" => {
-            let uri = build_uri("/api_keys".to_string());
-            r = block_on(do_http_get(uri, &neon_config));
-        }
-        s if s == "create" => {
-            let mut post_body: HashMap<String, String> = HashMap::new();
-            post_body.insert("key_name".to_string(), name.to_string());
-            let uri = build_uri("/api_keys".to_string());
-            r = block_on(do_http_post(uri, &post_body, &neon_config));
-        }
-        s if s == "revoke" => {
-            let uri = build_uri(format!("/api_keys/{}", name.to_string()));
-            r = block_on(do_http_delete(uri, &neon_config));
-        }
+) -> Result<(), NeonApiError> {
+    let client = NeonApiClient::new(neon_config)?;
+    let r: Result<Response, NeonApiError> = match action {
+        "list" => client.list_api_keys().await.and_then(as_response),
+        "create" => client.create_api_key(name).await.and_then(as_response),
+        "revoke" => client.revoke_api_key(name).await.and_then(as_response),
         _ => {
             panic!("Unknown Keys action.  Must specify correct aciton, use --help for list.");
         }
-    }
+    };
 
-    if format.is_empty() || format == "json" {
-        handle_http_result(r).ok();
-    } else if format == "table" {
-        let json_str = r.unwrap();
-        let parsed_array: Vec<Value> = serde_json::from_str(json_str.as_str()).unwrap();
-        print_generic_json_table(&parsed_array);
-    } else {
-        panic!("Unknown format: {format}");
-    }
+    handle_formatting_output(r, format, neon_config)
 }
 
 #[tokio::main]
 async fn perform_projects_action(
-    action: &String,
-    project: &String,
-    format: &String,
+    action: &str,
+    project: &str,
+    format: &str,
+    changes: &ProjectChanges,
     neon_config: &NeonSession,
-) {
-    let r: Result<String, Box<dyn Error>>;
+) -> Result<(), NeonApiError> {
+    let client = NeonApiClient::new(neon_config)?;
+    let r: Result<Response, NeonApiError>;
     if action == "list-projects" {
-        let uri = build_uri("/projects".to_string());
-        r = block_on(do_http_get(uri, neon_config));
+        let client = &client;
+        let fetch =
+            |page: Page| async move { client.list_projects(&page).await.and_then(as_response) };
+        return print_pages(fetch, format, neon_config).await;
     } else if action == "project-details" {
         // target/debug/neon-cli projects -a project-details -p white-voice-129396
-        let uri = build_uri(format!("/projects/{project}"));
-        r = block_on(do_http_get(uri, neon_config));
+        r = client.get_project(project).await.and_then(as_response);
     } else if action == "delete-project" {
-        let uri = build_uri(format!("/projects/{project}"));
-        r = block_on(do_http_delete(uri, neon_config));
+        r = client
+            .settle(client.delete_project(project).await.and_then(as_response))
+            .await;
+    } else if action == "create-project" {
+        // target/debug/neon-cli projects -a create-project --name analytics --region aws-us-east-2 --pg-version 16
+        check_limits(changes.min_cu, changes.max_cu)?;
+        let created = client.create_project(changes).await?;
+        client.wait_for(&created.operations).await?;
+        let uris = created.connection_uris.clone();
+        handle_formatting_output(as_response(created), format, neon_config)?;
+        return print_table_extras(format, neon_config, &[], &uris);
+    } else if action == "update-project" {
+        // target/debug/neon-cli projects -a update-project -p white-voice-129396 --allowed-ips 192.0.2.1 --quota active_time_seconds=3600
+        let create_only = [
+            ("--region", changes.region_id.is_some()),
+            ("--pg-version", changes.pg_version.is_some()),
+            ("--default-branch", changes.default_branch.is_some()),
+        ];
+        if let Some((flag, _)) = create_only.iter().find(|(_, given)| *given) {
+            return Err(NeonApiError::Usage(format!(
+                "{flag} can only be chosen by create-project"
+            )));
+        }
+        if changes.is_empty() {
+            return Err(NeonApiError::Usage(
+                "update-project needs at least one setting to change, e.g. --name or --allowed-ips"
+                    .to_string(),
+            ));
+        }
+        check_limits(changes.min_cu, changes.max_cu)?;
+        let mut updated = client.update_project(project, changes).await?;
+        client.wait_for(&updated.operations).await?;
+        updated.connection_uris = client.connection_uris(project).await?;
+        let uris = updated.connection_uris.clone();
+        handle_formatting_output(as_response(updated), format, neon_config)?;
+        return print_table_extras(format, neon_config, &[], &uris);
     } else {
         panic!("Unknown Project Action: {action}");
     }
-    handle_formatting_output(r, format, "projects");
+    handle_formatting_output(r, format, neon_config)
+}
+
+/// The autoscaling minimum can't be above the maximum.
+fn check_limits(min_cu: Option<f64>, max_cu: Option<f64>) -> Result<(), NeonApiError> {
+    match (min_cu, max_cu) {
+        (Some(min), Some(max)) if min > max => Err(NeonApiError::Usage(format!(
+            "--min-cu ({min}) can't be more than --max-cu ({max})"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// `-f table` for a created or updated project: the project, then how to connect to it.
+/// A table shows only a response's main item, so after it come the endpoints created
+/// along with it and the connection URIs.  Not when --query has picked something else.
+fn print_table_extras(
+    format: &str,
+    neon_config: &NeonSession,
+    endpoints: &[Endpoint],
+    uris: &[ConnectionUri],
+) -> Result<(), NeonApiError> {
+    if format != "table" || neon_config.query.is_some() {
+        return Ok(());
+    }
+    if !endpoints.is_empty() {
+        let endpoints = endpoints.iter().map(as_json);
+        let endpoints = endpoints.collect::<Result<Vec<_>, _>>()?;
+        let columns = EndpointResponse::COLUMNS;
+        output::print(&View::default().rows(endpoints, columns), format);
+    }
+    for uri in uris {
+        println!("Connection URI: {}", uri.connection_uri);
+    }
+    Ok(())
+}
+
+/// The role and database the role and database actions of `branch` work on, and for
+/// connection-uri, how to reach them.
+#[derive(Debug, Default)]
+struct BranchTargets {
+    role: String,
+    /// `--database` as given on the command line, rather than from the environment or the
+    /// config file, so that nothing is created, changed or deleted by accident.
+    database: Option<String>,
+    /// create-database, update-database: the role that owns the database.
+    owner: Option<String>,
+    /// update-database: the database's new name.
+    new_name: Option<String>,
+    /// The endpoint to connect through, instead of the branch's read-write one.
+    endpoint: Option<String>,
+    pooled: bool,
 }
 
 // % target/debug/neon-cli branch -a list-roles -p white-voice-129396 -b br-dry-silence-599905
 #[tokio::main]
 async fn perform_branches_action(
-    action: &String,
-    project: &String,
-    branch: &String,
-    format: &String,
-    role: &String,
+    action: &str,
+    project: &str,
+    branch: &str,
+    format: &str,
+    targets: &BranchTargets,
+    new_branch: &NewBranch,
     neon_config: &NeonSession,
-) {
-    let mut r: Result<String, Box<dyn Error>> = Ok("".to_string());
-    let mut rows_key = "branches";
+) -> Result<(), NeonApiError> {
+    let client = NeonApiClient::new(neon_config)?;
+    let r: Result<Response, NeonApiError>;
+    let role_actions = [
+        "role-details",
+        "role-create",
+        "role-delete",
+        "role-reset-password",
+        "role-reveal-password",
+        "connection-uri",
+    ];
+    if role_actions.contains(&action) && targets.role.is_empty() {
+        return Err(NeonApiError::Usage(format!(
+            "{action} needs the role to be named with -r"
+        )));
+    }
+    let role_name = targets.role.as_str();
+    let changes_database = ["create-database", "update-database", "delete-database"];
+    let database = match &targets.database {
+        Some(database) => database.as_str(),
+        None if changes_database.contains(&action) => {
+            return Err(NeonApiError::Usage(format!(
+                "{action} needs the database to be named with --database"
+            )));
+        }
+        // Looking things up can fall back to the session's database.
+        None => neon_config.database.as_str(),
+    };
 
     if action == "list-endpoints" {
-        let endpoint: String = format!("/projects/{project}/branches/{branch}/endpoints");
-        rows_key = "endpoints";
-        r = block_on(do_http_get(build_uri(endpoint), &neon_config));
+        r = client
+            .list_branch_endpoints(project, branch)
+            .await
+            .and_then(as_response);
     } else if action == "list-branches" {
         // target/debug/neon-cli branch -a list-branches -p white-voice-129396 -b br-dry-silence-599905
-        let endpoint: String = format!("/projects/{project}/branches");
-        r = block_on(do_http_get(build_uri(endpoint), &neon_config));
+        r = client.list_branches(project).await.and_then(as_response);
     } else if action == "list-roles" {
         // neon-cli branch -a list-roles -p white-voice-129396 -b br-dry-silence-599905
-        let endpoint: String = format!("/projects/{project}/branches/{branch}/roles");
-        rows_key = "roles";
-        r = block_on(do_http_get(build_uri(endpoint), &neon_config));
+        r = client
+            .list_roles(project, branch)
+            .await
+            .and_then(as_response);
     } else if action == "role-details" {
         // % target/debug/neon-cli branch -a role-details -p white-voice-129396 -b br-dry-silence-599905  -r tim
-        if role.is_empty() {
-            panic!("Role name is required");
-        }
-        rows_key = "role";
-        let endpoint: String = format!("/projects/{project}/branches/{branch}/roles/{role}");
-        r = block_on(do_http_get(build_uri(endpoint), &neon_config));
+        r = client
+            .get_role(project, branch, role_name)
+            .await
+            .and_then(as_response);
+    } else if action == "role-create" {
+        // The new role's password is only in this response; see role-reveal-password.
+        r = client
+            .settle(
+                client
+                    .create_role(project, branch, role_name)
+                    .await
+                    .and_then(as_response),
+            )
+            .await;
     } else if action == "role-delete" {
-        if role.is_empty() {
-            panic!("Role name is required");
-        }
-        let endpoint: String = format!("/projects/{project}/branches/{branch}/roles/{role}");
-        r = block_on(do_http_delete(build_uri(endpoint), &neon_config));
+        r = client
+            .settle(
+                client
+                    .delete_role(project, branch, role_name)
+                    .await
+                    .and_then(as_response),
+            )
+            .await;
+    } else if action == "role-reset-password" {
+        r = client
+            .settle(
+                client
+                    .reset_role_password(project, branch, role_name)
+                    .await
+                    .and_then(as_response),
+            )
+            .await;
+    } else if action == "role-reveal-password" {
+        r = client
+            .reveal_role_password(project, branch, role_name)
+            .await
+            .and_then(as_response);
+    } else if action == "connection-uri" {
+        // % target/debug/neon-cli branch -a connection-uri -p white-voice-129396 -b br-dry-silence-599905 -r tim --pooled --query connection_uri -f raw
+        r = client
+            .connection_uri(
+                project,
+                branch,
+                role_name,
+                database,
+                targets.endpoint.as_deref(),
+                targets.pooled,
+            )
+            .await
+            .and_then(as_response);
     } else if action == "branch-details" {
         // target/debug/neon-cli branch -a branch-details -p white-voice-129396 -b br-dry-silence-599905  -f table
-        let endpoint: String = format!("/projects/{project}/branches/{branch}");
-        r = block_on(do_http_get(build_uri(endpoint), &neon_config));
+        r = client
+            .get_branch(project, branch)
+            .await
+            .and_then(as_response);
     } else if action == "list-databases" {
-        let endpoint: String = format!("/projects/{project}/branches/{branch}/databases");
-        rows_key = "databases";
-        r = block_on(do_http_get(build_uri(endpoint), &neon_config));
+        r = client
+            .list_databases(project, branch)
+            .await
+            .and_then(as_response);
     } else if action == "database-details" {
-        let endpoint: String = format!(
-            "/projects/{project}/branches/{branch}/databases/{}",
-            neon_config.database
-        );
-        r = block_on(do_http_get(build_uri(endpoint), &neon_config));
+        // target/debug/neon-cli branch -a database-details -p white-voice-129396 -b br-dry-silence-599905 --database neondb
+        r = client
+            .get_database(project, branch, database)
+            .await
+            .and_then(as_response);
+    } else if action == "create-database" {
+        let Some(owner) = &targets.owner else {
+            return Err(NeonApiError::Usage(
+                "create-database needs the owning role, given with --owner".to_string(),
+            ));
+        };
+        r = client
+            .settle(
+                client
+                    .create_database(project, branch, database, owner)
+                    .await
+                    .and_then(as_response),
+            )
+            .await;
+    } else if action == "update-database" {
+        // target/debug/neon-cli branch -a update-database -p white-voice-129396 -b br-dry-silence-599905 --database neondb --name analytics --owner tim
+        if targets.new_name.is_none() && targets.owner.is_none() {
+            return Err(NeonApiError::Usage(
+                "update-database needs a new --name or --owner".to_string(),
+            ));
+        }
+        r = client
+            .settle(
+                client
+                    .update_database(
+                        project,
+                        branch,
+                        database,
+                        targets.new_name.as_deref(),
+                        targets.owner.as_deref(),
+                    )
+                    .await
+                    .and_then(as_response),
+            )
+            .await;
+    } else if action == "delete-database" {
+        r = client
+            .settle(
+                client
+                    .delete_database(project, branch, database)
+                    .await
+                    .and_then(as_response),
+            )
+            .await;
     } else if action == "delete-branch" {
-        let endpoint: String = format!("/projects/{project}/branches/{branch}");
-        r = block_on(do_http_delete(build_uri(endpoint), &neon_config));
+        r = client
+            .settle(
+                client
+                    .delete_branch(project, branch)
+                    .await
+                    .and_then(as_response),
+            )
+            .await;
     } else if action == "create-branch" {
+        // target/debug/neon-cli branch -a create-branch -p white-voice-129396 --parent br-dry-silence-599905 --name dev --with-endpoint
+        let created = client.create_branch(project, new_branch).await?;
+        client.wait_for(&created.operations).await?;
+        let endpoints = created.endpoints.clone();
+        let uris = created.connection_uris.clone().unwrap_or_default();
+        handle_formatting_output(as_response(created), format, neon_config)?;
+        return print_table_extras(format, neon_config, &endpoints, &uris);
     } else {
         panic!("Unknown Branch Action: {action}")
     }
-    handle_formatting_output(r, format, rows_key);
+    handle_formatting_output(r, format, neon_config)
+}
+
+/// Checks what create is about to send, wherever it came from, so that mistakes in
+/// --initconfig are reported as clearly as the typed flags'.
+fn check_endpoint_fields(fields: &Map<String, Value>) -> Result<(), NeonApiError> {
+    let usage = |message: String| Err(NeonApiError::Usage(message));
+    if !fields.contains_key("branch_id") {
+        return usage("create needs the branch for the endpoint, given with -b".to_string());
+    }
+    match fields.get("type").and_then(Value::as_str) {
+        Some("read_write" | "read_only") => {}
+        Some(other) => {
+            return usage(format!(
+                "The endpoint type has to be read_write or read_only, not {other}"
+            ))
+        }
+        None => return usage("create needs --type read_write or read_only".to_string()),
+    }
+    let mut limits = [None, None];
+    for (limit, key) in limits
+        .iter_mut()
+        .zip(["autoscaling_limit_min_cu", "autoscaling_limit_max_cu"])
+    {
+        if let Some(value) = fields.get(key) {
+            let cu = value
+                .as_f64()
+                .map(|cu| cu.to_string())
+                .ok_or(format!("expected a number of compute units, found {value}"))
+                .and_then(|cu| parse_cu(&cu))
+                .map_err(|e| NeonApiError::Usage(format!("{key}: {e}")))?;
+            *limit = Some(cu);
+        }
+    }
+    check_limits(limits[0], limits[1])
+}
+
+/// `--initconfig`: a JSON object, given inline or as @file.json.
+fn parse_initconfig(s: &str) -> Result<Map<String, Value>, String> {
+    let text = match s.strip_prefix('@') {
+        Some(path) => {
+            std::fs::read_to_string(path).map_err(|e| format!("can't read {path}: {e}"))?
+        }
+        None => s.to_string(),
+    };
+    match serde_json::from_str(&text) {
+        Ok(Value::Object(fields)) => Ok(fields),
+        Ok(_) => Err("expected a JSON object, like {\"type\": \"read_write\"}".to_string()),
+        Err(e) => Err(format!("invalid JSON: {e}")),
+    }
+}
+
+/// What endpoints create and update send.
+struct EndpointSettings {
+    /// --initconfig: the fields create starts from.
+    initconfig: Map<String, Value>,
+    /// The typed flags, which override --initconfig.
+    changes: EndpointChanges,
+    /// -b, when it was given.  Create only lets it override --initconfig's branch_id then,
+    /// not when it falls back to the session's branch.
+    branch: Option<String>,
 }
 
 #[tokio::main]
 async fn perform_endpoints_action(
-    action: &String,
-    project: &String,
-    endpoint: &String,
-    branch: &String,
-    config: &String, // the endpoint configuration, not the postgres setup
+    action: &str,
+    project: &str,
+    endpoint: &str,
+    branch: &str,
+    settings: &EndpointSettings, // the endpoint configuration, not the postgres setup
+    format: &str,
     neon_config: &NeonSession,
-) {
-    let r: Result<String, Box<dyn Error>>;
+) -> Result<(), NeonApiError> {
+    let client = NeonApiClient::new(neon_config)?;
+    let r: Result<Response, NeonApiError>;
     if action == "create" {
-        // target/debug/neon-cli endpoints -a create  -p white-voice-129396 --initconfig='{"type": "read_write","pooler_mode": "transaction","branch_id": "asdf","autoscaling_limit_min_cu": 2,"autoscaling_limit_max_cu": 2}' -b br-dry-silence-599905
-        let uri: String = format!("/projects/{project}/endpoints");
-        if config.is_empty() {
-            panic!("Missing or empty configuration for new endpoint.  Use the --initconfig param.")
-        }
-        let json_value: Result<Value, serde_json::Error> = serde_json::from_str(config);
-        let mut final_obj = json!({
-            "endpoint": json_value.unwrap(),
-        });
-        final_obj["endpoint"]["branch_id"] = json!(branch);
-        r = block_on(do_http_post_text(
-            build_uri(uri),
-            &final_obj.to_string(),
-            &neon_config,
-        ));
+        // target/debug/neon-cli endpoints -a create -p white-voice-129396 -b br-dry-silence-599905 --type read_write --min-cu 0.25 --max-cu 2 --pooler-mode transaction
+        let mut fields = settings.initconfig.clone();
+        fields.extend(settings.changes.to_fields());
+        // -b beats --initconfig, which beats the session's branch.
+        match &settings.branch {
+            Some(explicit) => {
+                fields.insert("branch_id".to_string(), json!(explicit));
+            }
+            None if !branch.is_empty() => {
+                fields
+                    .entry("branch_id".to_string())
+                    .or_insert(json!(branch));
+            }
+            None => {}
+        }
+        check_endpoint_fields(&fields)?;
+        let endpoint_config = Value::Object(fields);
+        r = client
+            .settle(
+                client
+                    .create_endpoint(project, &endpoint_config)
+                    .await
+                    .and_then(as_response),
+            )
+            .await;
     } else if action == "list" {
         // target/debug/neon-cli endpoints -a list -p white-voice-129396
-        let uri: String = format!("/projects/{project}/endpoints");
-        r = block_on(do_http_get(build_uri(uri), &neon_config));
+        r = client.list_endpoints(project).await.and_then(as_response);
     } else if action == "details" {
-        let uri: String = format!("/projects/{project}/endpoints/{endpoint}");
-        r = block_on(do_http_get(build_uri(uri), &neon_config));
+        r = client
+            .get_endpoint(project, endpoint)
+            .await
+            .and_then(as_response);
     } else if action == "delete" {
-        let uri: String = format!("/projects/{project}/endpoints/{endpoint}");
-        r = block_on(do_http_delete(build_uri(uri), &neon_config));
+        r = client
+            .settle(
+                client
+                    .delete_endpoint(project, endpoint)
+                    .await
+                    .and_then(as_response),
+            )
+            .await;
     } else if action == "start" || action == "suspend" {
         if endpoint.is_empty() {
-            panic!("Endpoint name is required");
+            return Err(NeonApiError::Usage(format!(
+                "{action} needs the endpoint, given with -e"
+            )));
+        }
+        let started = if action == "start" {
+            client.start_endpoint(project, endpoint).await
+        } else {
+            client.suspend_endpoint(project, endpoint).await
+        };
+        r = client.settle(started.and_then(as_response)).await;
+    } else if action == "restart" {
+        // target/debug/neon-cli endpoints -a restart -p white-voice-129396 -e ep-cool-darkness-123456
+        if endpoint.is_empty() {
+            return Err(NeonApiError::Usage(format!(
+                "{action} needs the endpoint, given with -e"
+            )));
+        }
+        let suspended = client.suspend_endpoint(project, endpoint).await;
+        client.settle(suspended.and_then(as_response)).await?;
+        let started = client.start_endpoint(project, endpoint).await;
+        r = client.settle(started.and_then(as_response)).await;
+    } else if action == "update" {
+        // target/debug/neon-cli endpoints -a update -p white-voice-129396 -e ep-cool-darkness-123456 --max-cu 4 --suspend-timeout 600
+        if endpoint.is_empty() {
+            return Err(NeonApiError::Usage(format!(
+                "{action} needs the endpoint, given with -e"
+            )));
         }
-        let uri: String = format!("/projects/{project}/endpoints/{endpoint}/{action}");
-        let post_body: HashMap<String, String> = HashMap::new();
-        r = block_on(do_http_post(build_uri(uri), &post_body, &neon_config));
+        let changes = &settings.changes;
+        let create_only = [
+            ("--type", changes.endpoint_type.is_some()),
+            ("--region", changes.region_id.is_some()),
+            ("--initconfig", !settings.initconfig.is_empty()),
+        ];
+        if let Some((flag, _)) = create_only.iter().find(|(_, given)| *given) {
+            return Err(NeonApiError::Usage(format!(
+                "{flag} can only be given to create"
+            )));
+        }
+        if changes.is_empty() {
+            return Err(NeonApiError::Usage(
+                "update needs at least one setting to change, e.g. --max-cu or --pooler-mode"
+                    .to_string(),
+            ));
+        }
+        check_limits(changes.min_cu, changes.max_cu)?;
+        r = client
+            .settle(
+                client
+                    .update_endpoint(project, endpoint, changes)
+                    .await
+                    .and_then(as_response),
+            )
+            .await;
     } else {
         panic!("Unknown Endpoints Action: {action}");
     }
-    handle_http_result(r).ok();
+    handle_formatting_output(r, format, neon_config)
 }
 
 #[tokio::main]
-async fn perform_consumption_action(limit: u32, cursor: &String, neon_config: &NeonSession) {
-    let endpoint: String = format!("/consumption/projects?cursor={cursor}&limit={limit}");
-    let r = block_on(do_http_get(build_uri(endpoint), &neon_config));
-    handle_http_result(r).ok();
+async fn perform_consumption_action(
+    format: &str,
+    neon_config: &NeonSession,
+) -> Result<(), NeonApiError> {
+    let client = &NeonApiClient::new(neon_config)?;
+    let fetch = |page: Page| async move { client.consumption(&page).await.and_then(as_response) };
+    print_pages(fetch, format, neon_config).await
 }
 
 #[tokio::main]
 async fn perform_operations_action(
-    action: &String,
-    project: &String,
-    operation: &String,
-    format: &String,
+    action: &str,
+    project: &str,
+    operation: &str,
+    format: &str,
     neon_config: &NeonSession,
-) {
-    let r: Result<String, Box<dyn Error>>;
+) -> Result<(), NeonApiError> {
+    let client = NeonApiClient::new(neon_config)?;
+    let r: Result<Response, NeonApiError>;
     if action == "list-operations" {
-        let endpoint: String = format!("/projects/{project}/operations");
-        r = block_on(do_http_get(build_uri(endpoint), &neon_config));
+        let client = &client;
+        let fetch = |page: Page| async move {
+            client
+                .list_operations(project, &page)
+                .await
+                .and_then(as_response)
+        };
+        return print_pages(fetch, format, neon_config).await;
     } else if action == "operation-details" {
         if operation.is_empty() {
             panic!("Operation ID is required");
         }
-        let endpoint: String = format!("/projects/{project}/operations/{operation}");
-        r = block_on(do_http_get(build_uri(endpoint), &neon_config));
+        r = client
+            .get_operation(project, operation)
+            .await
+            .and_then(as_response);
     } else {
         panic!("Unknown Operation Action: {action}");
     }
-    handle_formatting_output(r, format, "operations");
+    handle_formatting_output(r, format, neon_config)
+}
+
+/// Fetches a paginated list as `--all`, `--page-size`, `--max-items` and `--cursor` say, and
+/// prints it.  ndjson is printed a page at a time, so `--all` doesn't hold the whole list,
+/// unless `--sort-by` or `--query` need all of it first.
+async fn print_pages<F, Fut>(
+    fetch: F,
+    format: &str,
+    neon_config: &NeonSession,
+) -> Result<(), NeonApiError>
+where
+    F: FnMut(Page) -> Fut,
+    Fut: std::future::Future<Output = Result<Response, NeonApiError>>,
+{
+    let paging = &neon_config.paging;
+    let view = &neon_config.view;
+    if format == "ndjson" && view.sort_by.is_none() && neon_config.query.is_none() {
+        let mut print_page = |items: Vec<Value>| output::print(&view.rows(items, &[]), format);
+        paginate(paging, fetch, Some(&mut print_page)).await?;
+        return Ok(());
+    }
+    handle_formatting_output(paginate(paging, fetch, None).await, format, neon_config)
 }
 
-fn handle_formatting_output(r: Result<String, Box<dyn Error>>, format: &String, rows_key: &str) {
-    if format.is_empty() || format == "json" {
-        handle_http_result(r).ok();
-    } else if format == "table" {
-        let json_blob: Value = serde_json::from_str(&r.unwrap()).unwrap();
-        let candidate = json_blob[rows_key].as_array();
-        let mut rows: Vec<Value> = Vec::new();
-        if candidate.is_none() {
-            if let Some((_key, value)) = json_blob.as_object().unwrap().iter().next() {
-                rows = vec![value.clone()];
+/// Prints an API response.  json and yaml show the whole response; the row formats show
+/// its items, one row each; raw shows strings without quotes, one per line.  With --query,
+/// what the query picked out is printed instead.  Every API command's output goes through
+/// here.
+fn handle_formatting_output(
+    r: Result<Response, NeonApiError>,
+    format: &str,
+    neon_config: &NeonSession,
+) -> Result<(), NeonApiError> {
+    let mut response = r?;
+    if let Some(expression) = &neon_config.query {
+        let mut picked =
+            jsonquery::apply(expression, response.body).map_err(NeonApiError::Query)?;
+        response = Response {
+            body: match picked.len() {
+                1 => picked.remove(0),
+                _ => Value::Array(picked),
+            },
+            items: None,
+            columns: &[],
+        };
+    }
+    match format {
+        "" | "json" => println!("{}", to_string_pretty(&response.body)?),
+        "yaml" => print!("{}", output::to_yaml(&response.body)),
+        "raw" => print!("{}", output::raw(&response.body)),
+        _ => {
+            // Default columns keep tables narrow; the other formats show every field.
+            let defaults = if format == "table" {
+                response.columns
+            } else {
+                &[]
+            };
+            let view = &neon_config.view;
+            let mut rows = view.rows(response.rows(), defaults);
+            // ndjson can carry nested values as they are.
+            if format != "ndjson" {
+                rows = output::flatten(rows, view.max_depth);
             }
-        } else {
-            rows = json_blob[rows_key]
-                .as_array()
-                .expect("No rows found in response")
-                .to_vec();
+            output::print(&rows, format)
         }
-        print_generic_json_table(&rows);
-    } else {
-        panic!("Unknown format: {format}");
     }
+    Ok(())
+}
+
+/// What import needs to know about a table column to check CSV fields for it.
+#[derive(Debug, Clone, PartialEq)]
+struct ImportColumn {
+    name: String,
+    /// information_schema's data_type, e.g. "integer" or "timestamp with time zone".
+    data_type: String,
+    /// The underlying type's name: "_int4" for an int[] column, or the enum's name.
+    udt_name: String,
+    /// The allowed values, when the column is an enum.
+    labels: Vec<String>,
+}
+
+/// How `import` treats the CSV beyond the column mapping.
+struct ImportOptions {
+    delimiter: u8,
+    /// Table columns for the CSV fields, in order, instead of the header row's names.
+    columns: Vec<String>,
+    /// Field values that mean NULL.
+    nulls: Vec<String>,
+    /// Report bad rows and load the rest, instead of stopping at the first one.
+    report_errors: bool,
+}
+
+fn is_numeric(s: &str) -> bool {
+    if ["nan", "infinity", "+infinity", "-infinity"].contains(&s.to_lowercase().as_str()) {
+        return true;
+    }
+    let s = s.strip_prefix(['+', '-']).unwrap_or(s);
+    let (mantissa, exponent) = match s.split_once(['e', 'E']) {
+        Some((m, e)) => (m, Some(e.strip_prefix(['+', '-']).unwrap_or(e))),
+        None => (s, None),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = |p: &str| p.chars().all(|c| c.is_ascii_digit());
+    (!whole.is_empty() || !fraction.is_empty())
+        && digits(whole)
+        && digits(fraction)
+        && exponent.is_none_or(|e| !e.is_empty() && digits(e))
 }
 
+fn is_timestamp(s: &str) -> bool {
+    let formats = [
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M",
+    ];
+    ["infinity", "-infinity"].contains(&s)
+        || formats
+            .iter()
+            .any(|f| chrono::NaiveDateTime::parse_from_str(s, f).is_ok())
+        || chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").is_ok()
+}
+
+fn is_timestamptz(s: &str) -> bool {
+    let formats = ["%Y-%m-%d %H:%M:%S%.f%#z", "%Y-%m-%dT%H:%M:%S%.f%#z"];
+    chrono::DateTime::parse_from_rfc3339(s).is_ok()
+        || formats
+            .iter()
+            .any(|f| chrono::DateTime::parse_from_str(s, f).is_ok())
+        // Without an offset, the server reads it in the session's time zone.
+        || is_timestamp(s)
+}
+
+fn is_uuid(s: &str) -> bool {
+    let s = s
+        .strip_prefix('{')
+        .and_then(|s| s.strip_suffix('}'))
+        .unwrap_or(s);
+    let hex: String = s.chars().filter(|c| *c != '-').collect();
+    hex.len() == 32 && hex.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// bytea in hex, as `\x0a1b` or `0x0a1b`, normalized to the `\x` form Postgres reads.
+fn bytea_hex(s: &str) -> Option<String> {
+    let hex = s.strip_prefix("\\x").or_else(|| s.strip_prefix("0x"))?;
+    let valid = hex.len() % 2 == 0 && hex.chars().all(|c| c.is_ascii_hexdigit());
+    valid.then(|| format!("\\x{hex}"))
+}
+
+/// An array as either a Postgres literal (`{1,2,3}`), passed through, or a JSON array
+/// (`[1,2,3]`), converted to one.
+fn array_literal(s: &str) -> Option<String> {
+    if s.starts_with('{') && s.ends_with('}') {
+        return Some(s.to_string());
+    }
+    fn convert(v: &Value) -> Option<String> {
+        match v {
+            Value::Array(items) => {
+                let items: Option<Vec<String>> = items.iter().map(convert).collect();
+                Some(format!("{{{}}}", items?.join(",")))
+            }
+            Value::Null => Some("NULL".to_string()),
+            Value::String(s) => Some(quote_array_element(s)),
+            Value::Number(n) => Some(n.to_string()),
+            Value::Bool(b) => Some(b.to_string()),
+            Value::Object(_) => Some(quote_array_element(&v.to_string())),
+        }
+    }
+    match serde_json::from_str::<Value>(s) {
+        Ok(v @ Value::Array(_)) => convert(&v),
+        _ => None,
+    }
+}
+
+/// Checks each field of `record` against its column's type and adds it to `row`, so a bad
+/// value is reported with its line number instead of failing the COPY somewhere later.
+/// Fields matching one of `nulls` become NULL.  Types not checked here are passed on as-is
+/// for the server to check.
 #[inline(always)]
 fn add_conditionally(
     record: &StringRecord,
-    params: &mut Vec<Box<dyn ToSql + Sync>>,
-    column_types: &Vec<String>,
-) {
-    for i in 0..record.len() {
-        let ct = &column_types[i];
-        match ct.as_str() {
-            "text" | "character varying" | "varchar" => {
-                params.push(Box::new(
-                    record[i]
-                        .parse::<String>()
-                        .expect("Expected string in column."),
-                ));
-            }
-            "smallint" => {
-                params.push(Box::new(
-                    record[i]
-                        .parse::<i16>()
-                        .expect("Excpted smallint in column."),
-                ));
+    row: &mut Vec<Option<String>>,
+    columns: &[ImportColumn],
+    nulls: &[String],
+) -> Result<(), String> {
+    if record.len() != columns.len() {
+        return Err(format!(
+            "expected {} fields, found {}",
+            columns.len(),
+            record.len()
+        ));
+    }
+    for (column, field) in columns.iter().zip(record.iter()) {
+        if nulls.iter().any(|n| n == field) {
+            row.push(None);
+            continue;
+        }
+        let ct = column.data_type.as_str();
+        let value = match ct {
+            "smallint" => field.parse::<i16>().is_ok().then(|| field.to_string()),
+            "integer" | "int" | "int4" => field.parse::<i32>().is_ok().then(|| field.to_string()),
+            "bigint" | "int8" => field.parse::<i64>().is_ok().then(|| field.to_string()),
+            "real" | "float8" | "double precision" => {
+                field.parse::<f64>().is_ok().then(|| field.to_string())
             }
-            "integer" | "int" | "int4" => {
-                params.push(Box::new(
-                    record[i]
-                        .parse::<i32>()
-                        .expect("Expected integer in column."),
-                ));
+            "numeric" => is_numeric(field).then(|| field.to_string()),
+            "bool" | "boolean" => {
+                let known = [
+                    "t", "f", "true", "false", "y", "n", "yes", "no", "on", "off", "1", "0",
+                ];
+                known
+                    .contains(&field.to_lowercase().as_str())
+                    .then(|| field.to_string())
             }
-            "real" | "float8" => {
-                params.push(Box::new(
-                    record[i].parse::<f64>().expect("Expected float in column."),
-                ));
+            "date" => (["infinity", "-infinity"].contains(&field)
+                || chrono::NaiveDate::parse_from_str(field, "%Y-%m-%d").is_ok())
+            .then(|| field.to_string()),
+            "timestamp without time zone" => is_timestamp(field).then(|| field.to_string()),
+            "timestamp with time zone" => is_timestamptz(field).then(|| field.to_string()),
+            "uuid" => is_uuid(field).then(|| field.to_string()),
+            "json" | "jsonb" => serde_json::from_str::<Value>(field)
+                .is_ok()
+                .then(|| field.to_string()),
+            "bytea" => bytea_hex(field),
+            "ARRAY" => array_literal(field),
+            "USER-DEFINED" if !column.labels.is_empty() => {
+                if !column.labels.iter().any(|l| l == field) {
+                    return Err(format!(
+                        "expected one of {} in column {}, found {field:?}",
+                        column.labels.join(", "),
+                        column.name
+                    ));
+                }
+                Some(field.to_string())
             }
-            "bigint" | "int8" => {
-                params.push(Box::new(
-                    record[i].parse::<i64>().expect("Expeted bigint in column."),
+            _ => Some(field.to_string()),
+        };
+        match value {
+            Some(v) => row.push(Some(v)),
+            None => {
+                let ct = if ct == "ARRAY" { &column.udt_name } else { ct };
+                return Err(format!(
+                    "expected {ct} in column {}, found {field:?}",
+                    column.name
                 ));
             }
-            "bool" | "boolean" => {
-                params.push(Box::new(
-                    record[i]
-                        .parse::<bool>()
-                        .expect("Expected boolean in column."),
-                ));
+        }
+    }
+    Ok(())
+}
+
+/// Writes one row in COPY's text format: tab separated, with backslash escapes and `\N`
+/// for NULL.
+fn write_copy_row<W: Write>(out: &mut W, row: &[Option<String>]) -> std::io::Result<()> {
+    let mut line = String::new();
+    for (i, field) in row.iter().enumerate() {
+        if i > 0 {
+            line.push('\t');
+        }
+        let Some(field) = field else {
+            line.push_str("\\N");
+            continue;
+        };
+        for c in field.chars() {
+            match c {
+                '\\' => line.push_str("\\\\"),
+                '\t' => line.push_str("\\t"),
+                '\n' => line.push_str("\\n"),
+                '\r' => line.push_str("\\r"),
+                c => line.push(c),
             }
-            _ => {
-                panic!("Unknown column type: {ct}");
+        }
+    }
+    line.push('\n');
+    out.write_all(line.as_bytes())
+}
+
+/// Double-quotes an identifier for SQL, so its case and any odd characters are kept.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// A table named `name` or `schema.name`, each part quoted.
+fn quote_table(table: &str) -> String {
+    let parts: Vec<String> = table.split('.').map(quote_identifier).collect();
+    parts.join(".")
+}
+
+/// `SELECT *` from a table named `name` or `schema.name`.
+fn select_table_sql(table: &str) -> String {
+    format!("SELECT * FROM {}", quote_table(table))
+}
+
+/// Matches the CSV's column names to the table's columns, returning the table column for
+/// each CSV field.  Names are matched exactly, then ignoring case, since unquoted
+/// identifiers are folded to lower case by Postgres.
+fn map_import_columns(
+    names: &[String],
+    table_columns: &[ImportColumn],
+    table: &str,
+) -> Result<Vec<ImportColumn>, String> {
+    let mut mapped: Vec<ImportColumn> = Vec::with_capacity(names.len());
+    let mut missing: Vec<&str> = Vec::new();
+    for name in names {
+        let name = name.trim();
+        let column = table_columns.iter().find(|c| c.name == name).or_else(|| {
+            table_columns
+                .iter()
+                .find(|c| c.name.eq_ignore_ascii_case(name))
+        });
+        match column {
+            Some(c) if mapped.iter().any(|m| m.name == c.name) => {
+                return Err(format!("Column {} is listed more than once.", c.name));
             }
+            Some(c) => mapped.push(c.clone()),
+            None => missing.push(name),
         }
     }
+    if !missing.is_empty() {
+        let known: Vec<&str> = table_columns.iter().map(|c| c.name.as_str()).collect();
+        return Err(format!(
+            "CSV columns not found in table {table}: {}. The table has: {}.",
+            missing.join(", "),
+            known.join(", ")
+        ));
+    }
+    Ok(mapped)
 }
 
+/// Streams the CSV file into the table through `COPY ... FROM STDIN`, one row at a time,
+/// so files of any size load in constant memory.  Columns the CSV doesn't mention get
+/// their defaults.
 fn perform_import_action(
-    table: &String,
-    file: &String,
-    delimiter: &String,
+    table: &str,
+    file: &str,
+    options: &ImportOptions,
     neon_config: &NeonSession,
 ) -> Result<(), Box<dyn Error>> {
-    let mut client = neon_config.connect().expect("couldn't connect");
-
-    // grab type so that when we insert from CSV later, we can parse from StringRecord properly before INSERTs run
-    let q = format!(
-        "SELECT column_name, data_type FROM information_schema.columns WHERE table_name = '{}';",
-        table
-    );
-    let res = client.query(&q, &[]).unwrap();
-    let mut column_types: Vec<String> = Vec::new();
-    // grab column types for each column (a row here is a column description)
-    for row in &res {
-        let col_type: String = row.get("data_type");
-        column_types.push(col_type);
-    }
-
-    let rdr = csv::ReaderBuilder::new()
-        .delimiter(delimiter.as_bytes()[0])
-        .from_path(file);
-    let mut binding = rdr.unwrap();
-    let records = binding.records();
-
-    let mut params = Vec::<Box<dyn ToSql + Sync>>::new();
-    let num_cols: u16 = column_types.len() as u16;
-
-    #[inline(always)]
-    fn format_row_params(num_params: u32, row_num: u32) -> String {
-        let range = 1..=num_params; // Create a range from start to end (inclusive)
-        let mapped_values: String = range
-            .map(|i| format!("${}", i + row_num * num_params))
-            .collect::<Vec<String>>()
-            .join(",");
-        return format!("({mapped_values})");
-    }
-
-    // Build the parameterized portion of the insert statement separately
-    let vv = records
-        .enumerate()
-        .map(|(index, row)| {
-            let record = row.unwrap();
-            add_conditionally(&record, &mut params, &column_types);
-            format_row_params(num_cols as u32, index as u32)
-        })
-        .collect::<Vec<String>>()
-        .join(",");
+    let mut client = neon_config.connect()?;
 
-    // Rebuild the params from heap values
-    let param_values: Vec<&(dyn ToSql + Sync)> = params
+    // grab type so that when we read the CSV later, we can check each field before it is sent
+    let (schema, table_name) = match table.split_once('.') {
+        Some((schema, name)) => (Some(schema), name),
+        None => (None, table),
+    };
+    let res = client.query(
+        "SELECT c.column_name::text, c.data_type::text, c.udt_name::text,
+             coalesce(array_agg(e.enumlabel::text ORDER BY e.enumsortorder)
+                 FILTER (WHERE e.enumlabel IS NOT NULL), '{}')
+         FROM information_schema.columns c
+         LEFT JOIN pg_type t ON t.typname = c.udt_name AND t.typtype = 'e'
+             AND t.typnamespace = (SELECT oid FROM pg_namespace WHERE nspname = c.udt_schema)
+         LEFT JOIN pg_enum e ON e.enumtypid = t.oid
+         WHERE c.table_name = $1 AND c.table_schema = coalesce($2, current_schema())
+         GROUP BY c.column_name, c.data_type, c.udt_name, c.ordinal_position
+         ORDER BY c.ordinal_position",
+        &[&table_name, &schema],
+    )?;
+    // a row here is a column description
+    let table_columns: Vec<ImportColumn> = res
         .iter()
-        .map(|x: &Box<dyn ToSql + Sync>| &**x)
-        .collect::<Vec<_>>();
+        .map(|r| ImportColumn {
+            name: r.get(0),
+            data_type: r.get(1),
+            udt_name: r.get(2),
+            labels: r.get(3),
+        })
+        .collect();
+    if table_columns.is_empty() {
+        return Err(format!("Table {table} not found.").into());
+    }
 
-    let final_stmt = format!("INSERT INTO {table} VALUES {vv}");
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .from_path(file)?;
+    let names: Vec<String> = if options.columns.is_empty() {
+        rdr.headers()?.iter().map(|h| h.to_string()).collect()
+    } else {
+        options.columns.clone()
+    };
+    let columns = map_import_columns(&names, &table_columns, table)?;
+    let column_list: Vec<String> = columns.iter().map(|c| quote_identifier(&c.name)).collect();
 
-    client
-        .execute(final_stmt.as_str(), &param_values)
-        .expect("Couldn't execute prepared stmt.");
+    let copy = format!(
+        "COPY {} ({}) FROM STDIN",
+        quote_table(table),
+        column_list.join(", ")
+    );
+    let mut writer = client.copy_in(&copy)?;
+    let mut row: Vec<Option<String>> = Vec::with_capacity(columns.len());
+    let mut skipped = 0;
+    for result in rdr.records() {
+        let checked = result.map_err(|e| e.to_string()).and_then(|record| {
+            row.clear();
+            let line = record.position().map_or(0, |p| p.line());
+            add_conditionally(&record, &mut row, &columns, &options.nulls)
+                .map_err(|e| format!("{file} line {line}: {e}"))
+        });
+        match checked {
+            Ok(()) => write_copy_row(&mut writer, &row)?,
+            Err(e) if options.report_errors => {
+                eprintln!("Skipped {e}");
+                skipped += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let rows = writer.finish()?;
+    println!("Imported {rows} rows into {table}.");
+    if skipped > 0 {
+        eprintln!("Skipped {skipped} rows with errors.");
+    }
+    Ok(())
+}
 
+/// Writes the rows of `sql` to `output`, or to stdout when there is none.
+fn perform_export_action(
+    sql: &str,
+    output: &Option<String>,
+    options: &ExportOptions,
+    neon_config: &NeonSession,
+) -> Result<(), Box<dyn Error>> {
+    let mut client = neon_config.connect()?;
+    let out: Box<dyn Write + Send> = match output {
+        Some(path) => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+        None => Box::new(std::io::BufWriter::new(std::io::stdout())),
+    };
+    let rows = export::export(&mut client, sql, out, options).map_err(|e| match e
+        .downcast_ref::<postgres::Error>()
+    {
+        Some(pg) => pg_error_message(pg).into(),
+        None => e,
+    })?;
+    // With the data on stdout, the summary goes to stderr.
+    match output {
+        Some(path) => println!("Exported {rows} rows to {path}."),
+        None => eprintln!("Exported {rows} rows."),
+    }
     Ok(())
 }
 
+fn perform_profile_action(
+    action: &str,
+    name: &str,
+    profile: Profile,
+) -> Result<(), Box<dyn Error>> {
+    let mut file = ConfigFile::load(config_file_path())?;
+    if action != "list" && name.is_empty() {
+        return Err("Profile name is required, use --name".into());
+    }
+    match action {
+        "list" => {
+            let current = file.current_profile().unwrap_or("");
+            let rows: Vec<Value> = file
+                .profiles()
+                .iter()
+                .map(|(n, p)| {
+                    json!({
+                        "current": if n == current { "*" } else { "" },
+                        "name": n,
+                        "project": p.project.clone().unwrap_or_default(),
+                        "branch": p.branch.clone().unwrap_or_default(),
+                        "database": p.database.clone().unwrap_or_default(),
+                        "api_key": match (&p.api_key, &p.api_key_env) {
+                            (Some(_), _) => "(in config file)".to_string(),
+                            (None, Some(var)) => format!("${var}"),
+                            (None, None) => "".to_string(),
+                        },
+                    })
+                })
+                .collect();
+            output::print(&Rows::from_objects(&rows), "table");
+            return Ok(());
+        }
+        "add" => {
+            file.set_profile(name, &profile)?;
+            println!("Saved profile {name}.");
+        }
+        "remove" => {
+            file.remove_profile(name)?;
+            println!("Removed profile {name}.");
+        }
+        "use" => {
+            file.set_current_profile(name)?;
+            println!("Now using profile {name}.");
+        }
+        _ => return Err(format!("Unknown Profile Action: {action}").into()),
+    }
+    file.save()
+}
+
+/// Prints the error and exits non-zero, so scripts can tell a failed command from a successful one.
+fn exit_on_error<E: std::fmt::Display>(r: Result<(), E>) {
+    if let Err(e) = r {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+}
+
 fn main() {
     let cli = Cli::parse();
     let subcommand = cli.action;
-    dotenv().ok();
 
-    let config = initialize_env();
+    // The branch command's database actions only take --database from the command line.
+    let database = cli.database.clone();
+    let overrides = SessionOverrides {
+        profile: cli.profile,
+        api_key: cli.api_key,
+        connect_string: cli.connect_string,
+        database: cli.database,
+        api_url: cli.api_url,
+    };
+    if let Action::Profile {
+        action,
+        name,
+        api_key_env,
+        project,
+        branch,
+    } = subcommand
+    {
+        let profile = Profile {
+            api_key: overrides.api_key,
+            api_key_env,
+            project,
+            branch,
+            database: overrides.database,
+            connect_string: overrides.connect_string,
+            api_url: overrides.api_url,
+        };
+        let name = name.unwrap_or("".to_string());
+        exit_on_error(perform_profile_action(&action, &name, profile));
+        return;
+    }
+    let mut config = load_session(&overrides).unwrap_or_else(|e| {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    });
+    config.retry.max_retries = cli.max_retries;
+    config.retry.retry_writes = cli.retry_writes;
+    let timeout = Duration::from_secs(cli.timeout);
+    config.wait = cli.wait.then_some(timeout);
+    config.query = cli.query;
 
     match subcommand {
-        Action::Query { sql } => {
-            let c = config.connect().expect("couldn't connect");
-            let q: Query = Query { query: sql };
-            q.query(c);
+        Action::Query { sql, format } => {
+            exit_on_error(config.connect().and_then(|mut c| {
+                eprintln!("Executing query: {sql}");
+                let q: Query = Query { query: sql, format };
+                Ok(q.query(&mut c)?)
+            }));
+        }
+        Action::Shell {} => {
+            exit_on_error(
+                config
+                    .connect()
+                    .and_then(|c| shell::run(c, &config.database)),
+            );
         }
         Action::Projects {
             action,
             project,
             format,
+            view,
+            page,
+            name,
+            region,
+            pg_version,
+            default_branch,
+            min_cu,
+            max_cu,
+            history_retention,
+            allowed_ips,
+            quotas,
+            logical_replication,
         } => {
-            let p = project.unwrap_or("".to_string()); // project id
-            perform_projects_action(&action, &p, &format, &config)
+            config.view = view.into();
+            config.paging = page.into();
+            let p = project.unwrap_or(config.project.clone()); // project id
+            let changes = ProjectChanges {
+                name,
+                region_id: region,
+                pg_version,
+                default_branch,
+                min_cu,
+                max_cu,
+                history_retention_seconds: history_retention,
+                // --allowed-ips "" clears the list.
+                allowed_ips: allowed_ips
+                    .map(|ips| ips.into_iter().filter(|ip| !ip.is_empty()).collect()),
+                quotas,
+                logical_replication,
+            };
+            exit_on_error(perform_projects_action(
+                &action, &p, &format, &changes, &config,
+            ));
         }
         Action::Keys {
             action,
             name,
             format,
+            view,
         } => {
+            config.view = view.into();
             let name = name.unwrap_or("".to_string()); // name of the key to create
-            perform_keys_action(&action, &name, &format, &config);
+            exit_on_error(perform_keys_action(&action, &name, &format, &config));
         }
         Action::Branch {
             action,
@@ -607,11 +1629,43 @@ fn main() {
             branch,
             format,
             roles,
+            owner,
+            endpoint,
+            pooled,
+            parent,
+            parent_timestamp,
+            parent_lsn,
+            name,
+            with_endpoint,
+            view,
         } => {
-            let p = project.unwrap_or("".to_string());
-            let b: String = branch.unwrap_or("".to_string());
-            let r: String = roles.unwrap_or("".to_string());
-            perform_branches_action(&action, &p, &b, &format, &r, &config);
+            config.view = view.into();
+            let p = project.unwrap_or(config.project.clone());
+            let b: String = branch.unwrap_or(config.branch.clone());
+            let targets = BranchTargets {
+                role: roles.unwrap_or("".to_string()),
+                database: database.clone(),
+                owner,
+                new_name: name.clone(),
+                endpoint,
+                pooled,
+            };
+            let new_branch = NewBranch {
+                parent_id: parent,
+                name,
+                parent_timestamp,
+                parent_lsn,
+                with_endpoint,
+            };
+            exit_on_error(perform_branches_action(
+                &action,
+                &p,
+                &b,
+                &format,
+                &targets,
+                &new_branch,
+                &config,
+            ));
         }
         Action::Endpoints {
             action,
@@ -619,35 +1673,282 @@ fn main() {
             branch,
             endpoint,
             initconfig,
+            endpoint_type,
+            region,
+            min_cu,
+            max_cu,
+            suspend_timeout,
+            pooler_enabled,
+            pooler_mode,
+            disabled,
+            passwordless_access,
+            format,
+            view,
         } => {
-            let p = project.unwrap_or("".to_string());
-            let b: String = branch.unwrap_or("".to_string());
+            config.view = view.into();
+            if action == "restart" {
+                // The endpoint has to be suspended before it can be started again.
+                config.wait = Some(timeout);
+            }
+            let p = project.unwrap_or(config.project.clone());
+            let b: String = branch.clone().unwrap_or(config.branch.clone());
             let e: String = endpoint.unwrap_or("".to_string());
-            let params: String = initconfig.unwrap_or("".to_string()); // the json blob for endpoint config
-            perform_endpoints_action(&action, &p, &e, &b, &params, &config);
+            let settings = EndpointSettings {
+                branch,
+                initconfig: initconfig.unwrap_or_default(), // the json blob for endpoint config
+                changes: EndpointChanges {
+                    endpoint_type,
+                    region_id: region,
+                    min_cu,
+                    max_cu,
+                    suspend_timeout_seconds: suspend_timeout,
+                    pooler_enabled,
+                    pooler_mode,
+                    disabled,
+                    passwordless_access,
+                },
+            };
+            exit_on_error(perform_endpoints_action(
+                &action, &p, &e, &b, &settings, &format, &config,
+            ));
         }
-        Action::Consumption { limit, cursor } => {
-            let limit = limit.unwrap_or(16);
-            let cursor: String = cursor.unwrap_or("".to_string());
-            perform_consumption_action(limit, &cursor, &config);
+        Action::Consumption { page, format, view } => {
+            config.view = view.into();
+            config.paging = page.into();
+            // The report's pages have always been 16 projects long unless asked otherwise.
+            config.paging.first.limit.get_or_insert(16);
+            exit_on_error(perform_consumption_action(&format, &config));
         }
         Action::Operations {
             action,
             project,
             operation,
             format,
+            view,
+            page,
         } => {
-            let p = project.expect("Project ID is required for operations");
+            config.view = view.into();
+            config.paging = page.into();
+            let p = project
+                .or(Some(config.project.clone()).filter(|p| !p.is_empty()))
+                .expect("Project ID is required for operations");
             let o: String = operation.unwrap_or("".to_string());
-            perform_operations_action(&action, &p, &o, &format, &config);
+            exit_on_error(perform_operations_action(&action, &p, &o, &format, &config));
         }
         Action::Import {
             table,
             file,
             delimiter,
+            columns,
+            nulls,
+            on_error,
+        } => {
+            let options = ImportOptions {
+                delimiter: delimiter.unwrap_or(b','),
+                columns,
+                nulls,
+                report_errors: on_error == "report",
+            };
+            exit_on_error(perform_import_action(&table, &file, &options, &config));
+        }
+        Action::Export {
+            sql,
+            table,
+            output,
+            format,
+            delimiter,
+            quote,
+            no_header,
+            null,
+            batch_size,
         } => {
-            let _delim = delimiter.unwrap_or(",".to_string());
-            perform_import_action(&table, &file, &_delim, &config).unwrap();
+            let sql = sql.unwrap_or_else(|| select_table_sql(&table.unwrap_or_default()));
+            let format = format
+                .or_else(|| {
+                    output
+                        .as_deref()
+                        .and_then(export::format_for_path)
+                        .map(String::from)
+                })
+                .unwrap_or("csv".to_string());
+            let options = ExportOptions {
+                format,
+                delimiter,
+                quote: export::quote_style(&quote),
+                header: !no_header,
+                null,
+                batch_size,
+            };
+            exit_on_error(perform_export_action(&sql, &output, &options, &config));
+        }
+        Action::Profile { .. } => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(name: &str, data_type: &str) -> ImportColumn {
+        ImportColumn {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            udt_name: String::new(),
+            labels: Vec::new(),
+        }
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn check(columns: &[ImportColumn], fields: &[&str]) -> Result<Vec<Option<String>>, String> {
+        let mut row = Vec::new();
+        let nulls = names(&["", "NULL"]);
+        add_conditionally(
+            &StringRecord::from(fields.to_vec()),
+            &mut row,
+            columns,
+            &nulls,
+        )?;
+        Ok(row)
+    }
+
+    #[test]
+    fn delimiters_are_one_ascii_character() {
+        assert_eq!(parse_delimiter(";"), Ok(b';'));
+        assert_eq!(parse_delimiter("\t"), Ok(b'\t'));
+        assert!(parse_delimiter("").is_err());
+        assert!(parse_delimiter(";;").is_err());
+        assert!(parse_delimiter("§").is_err());
+    }
+
+    #[test]
+    fn export_table_names_are_quoted() {
+        assert_eq!(select_table_sql("users"), r#"SELECT * FROM "users""#);
+        assert_eq!(
+            select_table_sql(r#"Sales.order "items""#),
+            r#"SELECT * FROM "Sales"."order ""items""""#
+        );
+    }
+
+    #[test]
+    fn import_rows_are_checked_against_column_types() {
+        let columns = [
+            column("id", "integer"),
+            column("name", "text"),
+            column("ok", "boolean"),
+        ];
+        let row = check(&columns, &["7", "seven", "yes"]).unwrap();
+        assert_eq!(
+            row,
+            vec![Some("7".into()), Some("seven".into()), Some("yes".into())]
+        );
+
+        let err = check(&columns, &["seven", "7", "true"]).unwrap_err();
+        assert_eq!(err, r#"expected integer in column id, found "seven""#);
+        assert!(check(&columns, &["7"]).is_err());
+    }
+
+    #[test]
+    fn null_tokens_become_null() {
+        let columns = [column("n", "integer"), column("s", "text")];
+        assert_eq!(check(&columns, &["", "NULL"]).unwrap(), vec![None, None]);
+    }
+
+    #[test]
+    fn import_checks_richer_types() {
+        let good = [
+            ("numeric", "-12.50e3"),
+            ("numeric", "NaN"),
+            ("date", "2024-02-29"),
+            ("timestamp without time zone", "2023-05-17 18:19:16.5"),
+            ("timestamp with time zone", "2023-05-17T18:19:16Z"),
+            ("timestamp with time zone", "2023-05-17 18:19:16+05:30"),
+            ("uuid", "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11"),
+            ("jsonb", r#"{"a": [1, 2]}"#),
+            ("interval", "1 day"),
+        ];
+        for (data_type, field) in good {
+            assert!(
+                check(&[column("c", data_type)], &[field]).is_ok(),
+                "{data_type}: {field}"
+            );
+        }
+        let bad = [
+            ("numeric", "1.2.3"),
+            ("date", "2023-02-30"),
+            ("timestamp with time zone", "yesterday"),
+            ("uuid", "a0eebc99"),
+            ("json", "{nope"),
+            ("bytea", "\\xabc"),
+        ];
+        for (data_type, field) in bad {
+            assert!(
+                check(&[column("c", data_type)], &[field]).is_err(),
+                "{data_type}: {field}"
+            );
         }
     }
+
+    #[test]
+    fn import_normalizes_bytea_and_arrays() {
+        let row = check(
+            &[
+                column("b", "bytea"),
+                column("a", "ARRAY"),
+                column("p", "ARRAY"),
+            ],
+            &["0xDEAD", r#"[1, null, "x y"]"#, "{1,2}"],
+        )
+        .unwrap();
+        assert_eq!(row[0].as_deref(), Some("\\xDEAD"));
+        assert_eq!(row[1].as_deref(), Some(r#"{1,NULL,"x y"}"#));
+        assert_eq!(row[2].as_deref(), Some("{1,2}"));
+    }
+
+    #[test]
+    fn import_checks_enum_labels() {
+        let mut mood = column("mood", "USER-DEFINED");
+        mood.labels = names(&["sad", "happy"]);
+        assert!(check(&[mood.clone()], &["happy"]).is_ok());
+        let err = check(&[mood], &["meh"]).unwrap_err();
+        assert_eq!(
+            err,
+            r#"expected one of sad, happy in column mood, found "meh""#
+        );
+    }
+
+    #[test]
+    fn import_columns_are_matched_by_name() {
+        let table_columns = [column("id", "integer"), column("name", "text")];
+        let mapped = map_import_columns(&names(&["Name", " id "]), &table_columns, "people");
+        let mapped = mapped.unwrap();
+        assert_eq!(
+            mapped,
+            vec![table_columns[1].clone(), table_columns[0].clone()]
+        );
+
+        // A subset is fine; the rest get their defaults.
+        let subset = map_import_columns(&names(&["name"]), &table_columns, "people");
+        assert_eq!(subset.unwrap().len(), 1);
+
+        let err = map_import_columns(&names(&["id", "email", "age"]), &table_columns, "people")
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "CSV columns not found in table people: email, age. The table has: id, name."
+        );
+
+        let twice = map_import_columns(&names(&["id", "ID"]), &table_columns, "people");
+        assert!(twice.unwrap_err().contains("more than once"));
+    }
+
+    #[test]
+    fn copy_rows_escape_text_format_specials() {
+        let mut out = Vec::new();
+        let row = vec![Some("a\tb".to_string()), None, Some("c\\d\ne".to_string())];
+        write_copy_row(&mut out, &row).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "a\\tb\t\\N\tc\\\\d\\ne\n");
+    }
 }
@@ -0,0 +1,619 @@
+//! Serde models for the parts of the Neon v2 API this CLI uses.
+//!
+//! Every model keeps the fields it doesn't name in `extra`, so printing a model as JSON
+//! shows everything the API returned.  Fields the API may leave out or send as null are
+//! `Option`s that are left out again when printed, so output never shows values the API
+//! didn't send; the ids and names the CLI works with are required.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Project {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pg_version: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Branch {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_lsn: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_timestamp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Endpoint {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region_id: Option<String>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub endpoint_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub autoscaling_limit_min_cu: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub autoscaling_limit_max_cu: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pooler_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pooler_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub passwordless_access: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suspend_timeout_seconds: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Role {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protected: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Database {
+    pub id: i64,
+    pub name: String,
+    pub owner_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Operation {
+    pub id: String,
+    pub project_id: String,
+    pub action: String,
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failures_count: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_used_from_addr: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Returned once, when a key is created; `key` is the only time the secret is shown.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ApiKeyCreated {
+    pub id: i64,
+    pub key: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ProjectsResponse {
+    pub projects: Vec<Project>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ProjectResponse {
+    pub project: Project,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// What `POST /projects` returns: the project plus the branch, endpoint, role and database
+/// created with it.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ProjectCreated {
+    pub project: Project,
+    pub branch: Branch,
+    pub endpoints: Vec<Endpoint>,
+    pub operations: Vec<Operation>,
+    pub roles: Vec<Role>,
+    pub databases: Vec<Database>,
+    pub connection_uris: Vec<ConnectionUri>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// What `PATCH /projects/{id}` returns.  The API doesn't include connection URIs; the CLI
+/// looks them up and adds them, so an update prints the same as a create.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ProjectUpdated {
+    pub project: Project,
+    pub operations: Vec<Operation>,
+    #[serde(default)]
+    pub connection_uris: Vec<ConnectionUri>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The quotas a project's settings can limit, each in the unit its name ends with.
+pub const PROJECT_QUOTAS: [&str; 5] = [
+    "active_time_seconds",
+    "compute_time_seconds",
+    "written_data_bytes",
+    "data_transfer_bytes",
+    "logical_size_bytes",
+];
+
+/// Options for creating or updating a project.  Only what is set is sent, so an update
+/// leaves everything else as it is.  The region, Postgres version and default branch can
+/// only be chosen when the project is created.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ProjectChanges {
+    pub name: Option<String>,
+    pub region_id: Option<String>,
+    pub pg_version: Option<u32>,
+    pub default_branch: Option<String>,
+    /// Autoscaling limits, in compute units, for the project's new endpoints.
+    pub min_cu: Option<f64>,
+    pub max_cu: Option<f64>,
+    pub history_retention_seconds: Option<u32>,
+    /// The IP addresses and ranges allowed to connect; empty allows any.
+    pub allowed_ips: Option<Vec<String>>,
+    /// Limits by PROJECT_QUOTAS name.
+    pub quotas: Vec<(String, u64)>,
+    /// Turn on logical replication, which can't be turned off again.
+    pub logical_replication: bool,
+}
+
+impl ProjectChanges {
+    pub fn is_empty(&self) -> bool {
+        *self == ProjectChanges::default()
+    }
+
+    pub fn to_request(&self) -> Value {
+        let mut project = Map::new();
+        let mut insert = |key: &str, value: Value| project.insert(key.to_string(), value);
+        if let Some(name) = &self.name {
+            insert("name", json!(name));
+        }
+        if let Some(region) = &self.region_id {
+            insert("region_id", json!(region));
+        }
+        if let Some(version) = self.pg_version {
+            insert("pg_version", json!(version));
+        }
+        if let Some(branch) = &self.default_branch {
+            insert("branch", json!({ "name": branch }));
+        }
+        if let Some(seconds) = self.history_retention_seconds {
+            insert("history_retention_seconds", json!(seconds));
+        }
+        let mut endpoint_settings = Map::new();
+        if let Some(cu) = self.min_cu {
+            endpoint_settings.insert("autoscaling_limit_min_cu".to_string(), json!(cu));
+        }
+        if let Some(cu) = self.max_cu {
+            endpoint_settings.insert("autoscaling_limit_max_cu".to_string(), json!(cu));
+        }
+        if !endpoint_settings.is_empty() {
+            insert(
+                "default_endpoint_settings",
+                Value::Object(endpoint_settings),
+            );
+        }
+        let mut settings = Map::new();
+        if let Some(ips) = &self.allowed_ips {
+            settings.insert("allowed_ips".to_string(), json!({ "ips": ips }));
+        }
+        if !self.quotas.is_empty() {
+            let quota: Map<String, Value> = self
+                .quotas
+                .iter()
+                .map(|(name, limit)| (name.clone(), json!(limit)))
+                .collect();
+            settings.insert("quota".to_string(), Value::Object(quota));
+        }
+        if self.logical_replication {
+            settings.insert("enable_logical_replication".to_string(), json!(true));
+        }
+        if !settings.is_empty() {
+            insert("settings", Value::Object(settings));
+        }
+        json!({ "project": project })
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BranchesResponse {
+    pub branches: Vec<Branch>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BranchResponse {
+    pub branch: Branch,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BranchOperations {
+    pub branch: Branch,
+    pub operations: Vec<Operation>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ConnectionUri {
+    pub connection_uri: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// What `GET /projects/{id}/connection_uri` returns.  `uri` has no default, so a response
+/// without one is reported rather than printed as an empty string.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConnectionUriResponse {
+    pub uri: String,
+}
+
+/// What `POST /projects/{id}/branches` returns: the branch plus anything created with it.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BranchCreated {
+    pub branch: Branch,
+    pub endpoints: Vec<Endpoint>,
+    pub operations: Vec<Operation>,
+    pub roles: Vec<Role>,
+    pub databases: Vec<Database>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection_uris: Option<Vec<ConnectionUri>>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Options for creating a branch.  Without a parent, Neon branches from the primary branch;
+/// without a timestamp or LSN, from the parent's latest state.
+#[derive(Debug, Default, Clone)]
+pub struct NewBranch {
+    pub parent_id: Option<String>,
+    pub name: Option<String>,
+    pub parent_timestamp: Option<String>,
+    pub parent_lsn: Option<String>,
+    /// Also create a read-write endpoint so the branch can be connected to right away.
+    pub with_endpoint: bool,
+}
+
+impl NewBranch {
+    pub fn to_request(&self) -> Value {
+        let mut branch = Map::new();
+        let fields = [
+            ("parent_id", &self.parent_id),
+            ("name", &self.name),
+            ("parent_timestamp", &self.parent_timestamp),
+            ("parent_lsn", &self.parent_lsn),
+        ];
+        for (key, value) in fields {
+            if let Some(v) = value {
+                branch.insert(key.to_string(), Value::String(v.clone()));
+            }
+        }
+        let mut request = Map::new();
+        request.insert("branch".to_string(), Value::Object(branch));
+        if self.with_endpoint {
+            let endpoint = serde_json::json!({ "type": "read_write" });
+            request.insert("endpoints".to_string(), Value::Array(vec![endpoint]));
+        }
+        Value::Object(request)
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct EndpointsResponse {
+    pub endpoints: Vec<Endpoint>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct EndpointResponse {
+    pub endpoint: Endpoint,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct EndpointOperations {
+    pub endpoint: Endpoint,
+    pub operations: Vec<Operation>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Endpoint settings from the command line's typed flags.  Only what is set is sent, so an
+/// update leaves everything else as it is.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct EndpointChanges {
+    /// "read_write" or "read_only"; only chosen when the endpoint is created.
+    pub endpoint_type: Option<String>,
+    /// Only chosen when the endpoint is created.
+    pub region_id: Option<String>,
+    /// Autoscaling limits, in compute units.
+    pub min_cu: Option<f64>,
+    pub max_cu: Option<f64>,
+    /// Idle seconds before the endpoint is suspended; 0 is Neon's default and -1 never.
+    pub suspend_timeout_seconds: Option<i64>,
+    pub pooler_enabled: Option<bool>,
+    /// "transaction" or "session".
+    pub pooler_mode: Option<String>,
+    pub disabled: Option<bool>,
+    pub passwordless_access: Option<bool>,
+}
+
+impl EndpointChanges {
+    pub fn is_empty(&self) -> bool {
+        *self == EndpointChanges::default()
+    }
+
+    /// The settings as the fields of an endpoint object.
+    pub fn to_fields(&self) -> Map<String, Value> {
+        let mut fields = Map::new();
+        let mut insert = |key: &str, value: Option<Value>| {
+            if let Some(value) = value {
+                fields.insert(key.to_string(), value);
+            }
+        };
+        insert("type", self.endpoint_type.as_ref().map(|t| json!(t)));
+        insert("region_id", self.region_id.as_ref().map(|r| json!(r)));
+        insert("autoscaling_limit_min_cu", self.min_cu.map(|cu| json!(cu)));
+        insert("autoscaling_limit_max_cu", self.max_cu.map(|cu| json!(cu)));
+        insert(
+            "suspend_timeout_seconds",
+            self.suspend_timeout_seconds.map(|s| json!(s)),
+        );
+        insert("pooler_enabled", self.pooler_enabled.map(|b| json!(b)));
+        insert("pooler_mode", self.pooler_mode.as_ref().map(|m| json!(m)));
+        insert("disabled", self.disabled.map(|b| json!(b)));
+        insert(
+            "passwordless_access",
+            self.passwordless_access.map(|b| json!(b)),
+        );
+        fields
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RolesResponse {
+    pub roles: Vec<Role>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RoleResponse {
+    pub role: Role,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RoleOperations {
+    pub role: Role,
+    pub operations: Vec<Operation>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// What creating a role or resetting its password returns: the role carries the new
+/// password, which the API doesn't show anywhere else except `reveal_password`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RoleCreated {
+    pub role: Role,
+    pub operations: Vec<Operation>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DatabaseOperations {
+    pub database: Database,
+    pub operations: Vec<Operation>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// What `reveal_password` returns.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RolePassword {
+    pub password: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DatabasesResponse {
+    pub databases: Vec<Database>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DatabaseResponse {
+    pub database: Database,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct OperationsResponse {
+    pub operations: Vec<Operation>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct OperationResponse {
+    pub operation: Operation,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The consumption report.  Only the per-project list is named; the rest of its shape
+/// depends on the plan and is kept in `extra`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ConsumptionResponse {
+    pub projects: Vec<Value>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Where a response keeps what the row formats (table, csv, ndjson, ...) print: the key of
+/// its list or single object, or None when the response is itself the list or object.
+/// `COLUMNS` are the fields `-f table` shows when `--columns` isn't given; empty shows all.
+pub trait Listing {
+    const ITEMS: Option<&'static str>;
+    const COLUMNS: &'static [&'static str];
+}
+
+const PROJECT_COLUMNS: &[&str] = &["id", "name", "region_id", "pg_version", "created_at"];
+const BRANCH_COLUMNS: &[&str] = &[
+    "id",
+    "name",
+    "parent_id",
+    "primary",
+    "current_state",
+    "created_at",
+];
+const ENDPOINT_COLUMNS: &[&str] = &[
+    "id",
+    "branch_id",
+    "type",
+    "current_state",
+    "host",
+    "autoscaling_limit_min_cu",
+    "autoscaling_limit_max_cu",
+];
+const ROLE_COLUMNS: &[&str] = &["name", "branch_id", "protected", "created_at"];
+const ROLE_PASSWORD_COLUMNS: &[&str] = &["name", "branch_id", "password", "created_at"];
+const DATABASE_COLUMNS: &[&str] = &["id", "name", "owner_name", "branch_id", "created_at"];
+const OPERATION_COLUMNS: &[&str] = &[
+    "id",
+    "action",
+    "status",
+    "branch_id",
+    "endpoint_id",
+    "created_at",
+];
+const KEY_COLUMNS: &[&str] = &["id", "name", "created_at", "last_used_at"];
+
+macro_rules! listing {
+    ($($response:ty => $items:expr, $columns:expr;)*) => {
+        $(impl Listing for $response {
+            const ITEMS: Option<&'static str> = $items;
+            const COLUMNS: &'static [&'static str] = $columns;
+        })*
+    };
+}
+
+listing! {
+    Vec<ApiKey> => None, KEY_COLUMNS;
+    ApiKey => None, KEY_COLUMNS;
+    ApiKeyCreated => None, &[];
+    ProjectsResponse => Some("projects"), PROJECT_COLUMNS;
+    ProjectResponse => Some("project"), PROJECT_COLUMNS;
+    ProjectCreated => Some("project"), PROJECT_COLUMNS;
+    ProjectUpdated => Some("project"), PROJECT_COLUMNS;
+    BranchesResponse => Some("branches"), BRANCH_COLUMNS;
+    BranchResponse => Some("branch"), BRANCH_COLUMNS;
+    BranchOperations => Some("branch"), BRANCH_COLUMNS;
+    BranchCreated => Some("branch"), BRANCH_COLUMNS;
+    EndpointsResponse => Some("endpoints"), ENDPOINT_COLUMNS;
+    EndpointResponse => Some("endpoint"), ENDPOINT_COLUMNS;
+    EndpointOperations => Some("endpoint"), ENDPOINT_COLUMNS;
+    RolesResponse => Some("roles"), ROLE_COLUMNS;
+    RoleResponse => Some("role"), ROLE_COLUMNS;
+    RoleOperations => Some("role"), ROLE_COLUMNS;
+    RoleCreated => Some("role"), ROLE_PASSWORD_COLUMNS;
+    RolePassword => None, &["password"];
+    ConnectionUri => None, &["connection_uri"];
+    DatabasesResponse => Some("databases"), DATABASE_COLUMNS;
+    DatabaseResponse => Some("database"), DATABASE_COLUMNS;
+    DatabaseOperations => Some("database"), DATABASE_COLUMNS;
+    OperationsResponse => Some("operations"), OPERATION_COLUMNS;
+    OperationResponse => Some("operation"), OPERATION_COLUMNS;
+    ConsumptionResponse => Some("projects"), &[];
+}
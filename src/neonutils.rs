@@ -1,100 +1,539 @@
-#![allow(dead_code)]
-
-use chrono::Utc;
-use serde_json::Value;
-use comfy_table::*;
-use comfy_table::{modifiers::UTF8_ROUND_CORNERS, presets::UTF8_FULL};
-use std::{collections::HashMap, vec::Vec};
+use chrono::{Duration, NaiveDate};
+use postgres::types::{FromSql, Kind, Type};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::vec::Vec;
 
 /// The postgres-crate does not provide a default mapping to fallback to String for all
 /// types: row.get is generic and without a type assignment the FromSql-Trait cannot be inferred.
-/// This function matches over the current column-type and does a manual conversion
-pub fn reflective_get(row: &postgres::Row, index: usize) -> String {
-    let column_type = row.columns().get(index).map(|c| c.type_().name()).unwrap();
-    // see https://docs.rs/sqlx/0.4.0-beta.1/sqlx/postgres/types/index.html
-    let value = match column_type {
-        "bool" => {
-            let v: Option<bool> = row.get(index);
-            v.map(|v| v.to_string())
-        }
-        "varchar" | "char(n)" | "text" | "name" => {
-            let v: Option<String> = row.get(index);
-            v
-        }
-        // "char" => {
-        //     let v: i8 = row.get(index);
-        // }
-        "int2" | "smallserial" | "smallint" => {
-            let v: Option<i16> = row.get(index);
-            v.map(|v| v.to_string())
-        }
-        "int" | "int4" | "serial" => {
-            let v: Option<i32> = row.get(index);
-            v.map(|v| v.to_string())
-        }
-        "int8" | "bigserial" | "bigint" => {
-            let v: Option<i64> = row.get(index);
-            v.map(|v| v.to_string())
-        }
-        "float4" | "real" => {
-            let v: Option<f32> = row.get(index);
-            v.map(|v| v.to_string())
-        }
-        "float8" | "double precision" => {
-            let v: Option<f64> = row.get(index);
-            v.map(|v| v.to_string())
-        }
-        "timestamp" | "timestamptz" => {
-            // with-chrono feature is needed for this
-            let v: Option<chrono::DateTime<Utc>> = row.get(index);
-            v.map(|v| v.to_string())
-        }
-        &_ => Some("CANNOT PARSE".to_string()),
+/// This function takes the column's raw binary value and decodes it by the column-type by hand.
+/// NULL is None, for output that has to tell it apart from ''.
+pub fn reflective_get_opt(row: &postgres::Row, index: usize) -> Option<String> {
+    let column_type = row.columns().get(index).map(|c| c.type_()).unwrap();
+    let raw: Option<Raw> = row.get(index);
+    raw.map(|Raw(bytes)| decode_text(column_type, bytes).unwrap_or("CANNOT PARSE".to_string()))
+}
+
+/// Whether reflective_get_opt can show values of this type.  When a result has a column that it
+/// can't, run the query through query_as_text instead.
+pub fn can_decode(ty: &Type) -> bool {
+    match ty.kind() {
+        Kind::Array(element) => can_decode(element),
+        Kind::Domain(base) => can_decode(base),
+        Kind::Enum(_) => true,
+        _ => DECODABLE.contains(ty),
+    }
+}
+
+const DECODABLE: [Type; 34] = [
+    Type::BOOL,
+    Type::CHAR,
+    Type::INT2,
+    Type::INT4,
+    Type::INT8,
+    Type::OID,
+    Type::FLOAT4,
+    Type::FLOAT8,
+    Type::NUMERIC,
+    Type::MONEY,
+    Type::TEXT,
+    Type::VARCHAR,
+    Type::BPCHAR,
+    Type::NAME,
+    Type::UNKNOWN,
+    Type::XML,
+    Type::JSON,
+    Type::JSONB,
+    Type::JSONPATH,
+    Type::UUID,
+    Type::BYTEA,
+    Type::DATE,
+    Type::TIME,
+    Type::TIMETZ,
+    Type::TIMESTAMP,
+    Type::TIMESTAMPTZ,
+    Type::INTERVAL,
+    Type::INET,
+    Type::CIDR,
+    Type::MACADDR,
+    Type::MACADDR8,
+    Type::XID,
+    Type::CID,
+    Type::PG_LSN,
+];
+
+/// Runs `sql` with the simple query protocol, which returns every value in the server's own
+/// text representation, so results with types reflective_get_opt doesn't know can still be shown.
+pub fn query_as_text(
+    client: &mut postgres::Client,
+    sql: &str,
+) -> Result<Vec<Vec<Option<String>>>, postgres::Error> {
+    let mut rows = Vec::new();
+    for message in client.simple_query(sql)? {
+        if let postgres::SimpleQueryMessage::Row(row) = message {
+            let values = (0..row.len())
+                .map(|i| row.get(i).map(str::to_string))
+                .collect();
+            rows.push(values);
+        }
+    }
+    Ok(rows)
+}
+
+/// A column's undecoded binary value.
+struct Raw<'a>(&'a [u8]);
+
+impl<'a> FromSql<'a> for Raw<'a> {
+    fn from_sql(
+        _ty: &Type,
+        raw: &'a [u8],
+    ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+        Ok(Raw(raw))
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+}
+
+/// Reads big-endian integers off the front of a binary value.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn i16(&mut self) -> Option<i16> {
+        self.take(2).map(|b| i16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn i32(&mut self) -> Option<i32> {
+        self.take(4)
+            .and_then(|b| b.try_into().ok())
+            .map(i32::from_be_bytes)
+    }
+
+    fn i64(&mut self) -> Option<i64> {
+        self.take(8)
+            .and_then(|b| b.try_into().ok())
+            .map(i64::from_be_bytes)
+    }
+}
+
+/// Decodes a binary value into the text Postgres itself would show, or None for a type
+/// can_decode rejects.
+fn decode_text(ty: &Type, raw: &[u8]) -> Option<String> {
+    match ty.kind() {
+        Kind::Array(element) => return decode_array(element, raw),
+        Kind::Domain(base) => return decode_text(base, raw),
+        Kind::Enum(_) => return Some(String::from_utf8_lossy(raw).to_string()),
+        _ => {}
+    }
+    let mut r = Reader(raw);
+    let text = match *ty {
+        Type::BOOL => (*raw.first()? != 0).to_string(),
+        Type::CHAR => (*raw.first()? as char).to_string(),
+        Type::INT2 => r.i16()?.to_string(),
+        Type::INT4 => r.i32()?.to_string(),
+        Type::INT8 => r.i64()?.to_string(),
+        Type::OID | Type::XID | Type::CID => (r.i32()? as u32).to_string(),
+        Type::FLOAT4 => decode_float(f32::from_sql(ty, raw).ok()?),
+        Type::FLOAT8 => decode_float(f64::from_sql(ty, raw).ok()?),
+        Type::NUMERIC => decode_numeric(raw)?,
+        Type::MONEY => decode_money(r.i64()?),
+        Type::TEXT
+        | Type::VARCHAR
+        | Type::BPCHAR
+        | Type::NAME
+        | Type::UNKNOWN
+        | Type::XML
+        | Type::JSON => String::from_utf8_lossy(raw).to_string(),
+        // jsonb and jsonpath start with a format version byte.
+        Type::JSONB | Type::JSONPATH => String::from_utf8_lossy(raw.get(1..)?).to_string(),
+        Type::UUID => decode_uuid(raw)?,
+        Type::BYTEA => decode_bytea(raw),
+        Type::DATE => decode_date(r.i32()?)?,
+        Type::TIME => decode_clock(r.i64()?.unsigned_abs()),
+        Type::TIMETZ => {
+            let time = decode_clock(r.i64()?.unsigned_abs());
+            // The zone is stored as seconds west of UTC.
+            format!("{time}{}", decode_offset(-r.i32()?))
+        }
+        Type::TIMESTAMP => decode_timestamp(r.i64()?)?,
+        // Binary timestamptz values are always UTC.
+        Type::TIMESTAMPTZ => match decode_timestamp(r.i64()?)? {
+            t if t.ends_with("infinity") => t,
+            t => format!("{t}+00"),
+        },
+        Type::INTERVAL => {
+            let micros = r.i64()?;
+            let days = r.i32()?;
+            decode_interval(r.i32()?, days, micros)
+        }
+        Type::INET | Type::CIDR => decode_inet(raw)?,
+        Type::MACADDR | Type::MACADDR8 => {
+            let parts: Vec<String> = raw.iter().map(|b| format!("{b:02x}")).collect();
+            parts.join(":")
+        }
+        Type::PG_LSN => {
+            let lsn = r.i64()? as u64;
+            format!("{:X}/{:X}", lsn >> 32, lsn & 0xFFFF_FFFF)
+        }
+        _ => return None,
     };
-    value.unwrap_or("".to_string())
+    Some(text)
+}
+
+/// 2000-01-01, which Postgres counts dates and timestamps from.
+fn pg_epoch() -> NaiveDate {
+    NaiveDate::from_ymd_opt(2000, 1, 1).unwrap()
+}
+
+/// Formats a float4 at its own precision, so 0.1 stays 0.1 rather than widening first.
+fn decode_float<F: Into<f64> + Copy + ToString>(f: F) -> String {
+    let wide: f64 = f.into();
+    if wide.is_infinite() {
+        (if wide > 0.0 { "Infinity" } else { "-Infinity" }).to_string()
+    } else {
+        f.to_string()
+    }
+}
+
+/// numeric is sign, weight and scale followed by base-10000 digits.
+fn decode_numeric(raw: &[u8]) -> Option<String> {
+    let mut r = Reader(raw);
+    let ndigits = r.i16()? as usize;
+    let weight = r.i16()? as i32;
+    let sign = r.i16()? as u16;
+    let dscale = r.i16()? as usize;
+    match sign {
+        0xC000 => return Some("NaN".to_string()),
+        0xD000 => return Some("Infinity".to_string()),
+        0xF000 => return Some("-Infinity".to_string()),
+        _ => {}
+    }
+    let digits: Vec<i16> = (0..ndigits).map(|_| r.i16()).collect::<Option<_>>()?;
+    let digit = |i: i32| {
+        if i < 0 {
+            0
+        } else {
+            digits.get(i as usize).copied().unwrap_or(0)
+        }
+    };
+
+    let mut text = String::new();
+    if sign == 0x4000 {
+        text.push('-');
+    }
+    if weight < 0 {
+        text.push('0');
+    } else {
+        text.push_str(&digit(0).to_string());
+        for i in 1..=weight {
+            text.push_str(&format!("{:04}", digit(i)));
+        }
+    }
+    if dscale > 0 {
+        let mut fraction = String::new();
+        let mut i = weight + 1;
+        while fraction.len() < dscale {
+            fraction.push_str(&format!("{:04}", digit(i)));
+            i += 1;
+        }
+        fraction.truncate(dscale);
+        text.push('.');
+        text.push_str(&fraction);
+    }
+    Some(text)
+}
+
+/// money is a count of cents; shown the way Postgres does in the C and en_US locales.
+fn decode_money(cents: i64) -> String {
+    let units = (cents / 100).unsigned_abs().to_string();
+    let groups: Vec<&str> = units
+        .as_bytes()
+        .rchunks(3)
+        .rev()
+        .map(|g| std::str::from_utf8(g).unwrap_or_default())
+        .collect();
+    let grouped = groups.join(",");
+    let sign = if cents < 0 { "-" } else { "" };
+    format!("{sign}${grouped}.{:02}", (cents % 100).unsigned_abs())
 }
 
+fn decode_uuid(raw: &[u8]) -> Option<String> {
+    if raw.len() != 16 {
+        return None;
+    }
+    let hex: String = raw.iter().map(|b| format!("{b:02x}")).collect();
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    ))
+}
 
-pub fn print_generic_json_table(rows: &Vec<Value>) {
-    let mut table = Table::new();
-    table
-        .load_preset(UTF8_FULL)
-        .apply_modifier(UTF8_ROUND_CORNERS)
-        .set_content_arrangement(ContentArrangement::Dynamic);
-    let mut saw_first_row = false;
+fn decode_bytea(raw: &[u8]) -> String {
+    let hex: String = raw.iter().map(|b| format!("{b:02x}")).collect();
+    format!("\\x{hex}")
+}
 
-    for row in rows {
-        for (_key, _value) in row.as_object().unwrap() {
-            if !saw_first_row {
-                let col_names: Vec<String> =
-                row.as_object().unwrap().iter().map(|c| c.0.to_string()).collect::<Vec<String>>();
-                table.set_header(col_names);
-                saw_first_row = true;
-            }
+fn decode_date(days: i32) -> Option<String> {
+    Some(match days {
+        i32::MAX => "infinity".to_string(),
+        i32::MIN => "-infinity".to_string(),
+        _ => pg_epoch()
+            .checked_add_signed(Duration::days(days as i64))?
+            .to_string(),
+    })
+}
+
+/// HH:MM:SS, plus the fraction of a second only when there is one, as Postgres prints it.
+fn decode_clock(micros: u64) -> String {
+    let seconds = micros / 1_000_000;
+    let mut text = format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    );
+    let fraction = micros % 1_000_000;
+    if fraction != 0 {
+        let digits = format!("{fraction:06}");
+        text.push('.');
+        text.push_str(digits.trim_end_matches('0'));
+    }
+    text
+}
+
+fn decode_timestamp(micros: i64) -> Option<String> {
+    const DAY: i64 = 86_400_000_000;
+    Some(match micros {
+        i64::MAX => "infinity".to_string(),
+        i64::MIN => "-infinity".to_string(),
+        _ => {
+            let date = pg_epoch().checked_add_signed(Duration::days(micros.div_euclid(DAY)))?;
+            let clock = decode_clock(micros.rem_euclid(DAY) as u64);
+            format!("{date} {clock}")
         }
-        let row_strs: Vec<String> = row.as_object().unwrap().iter().map(|c| c.1.to_string()).collect::<Vec<String>>();
-        table.add_row(row_strs);
+    })
+}
+
+/// A UTC offset in seconds east, as +HH, +HH:MM or +HH:MM:SS.
+fn decode_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let s = seconds.unsigned_abs();
+    let (minutes, seconds) = (s % 3600 / 60, s % 60);
+    let mut text = format!("{sign}{:02}", s / 3600);
+    if minutes != 0 || seconds != 0 {
+        text.push_str(&format!(":{minutes:02}"));
+    }
+    if seconds != 0 {
+        text.push_str(&format!(":{seconds:02}"));
     }
-    println!("{table}");
+    text
 }
 
-pub fn jsonstring_to_map(json_str: &String) -> Box<HashMap<String,String>>{
-    let v: Value = serde_json::from_str(json_str).unwrap();
-    let mut map: HashMap<String, String> = HashMap::new();
-    if let Value::Object(object) = v {
-        for (key, value) in object {
-            if let Value::String(string_value) = value {
-                map.insert(key, string_value);
-            }
+/// Formats an interval like Postgres's default output style, e.g. "1 year 2 mons 3 days 04:05:06".
+fn decode_interval(months: i32, days: i32, micros: i64) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    let mut unit = |n: i32, singular: &str, plural: &str| {
+        if n != 0 {
+            let name = if n == 1 { singular } else { plural };
+            parts.push(format!("{n} {name}"));
+        }
+    };
+    unit(months / 12, "year", "years");
+    unit(months % 12, "mon", "mons");
+    unit(days, "day", "days");
+    if micros != 0 || parts.is_empty() {
+        let sign = if micros < 0 { "-" } else { "" };
+        parts.push(format!("{sign}{}", decode_clock(micros.unsigned_abs())));
+    }
+    parts.join(" ")
+}
+
+/// inet and cidr are family, netmask bits, a cidr flag and the address bytes.
+fn decode_inet(raw: &[u8]) -> Option<String> {
+    let (family, bits, is_cidr) = (*raw.first()?, *raw.get(1)?, *raw.get(2)? != 0);
+    let address = raw.get(4..)?;
+    let (text, max_bits) = match family {
+        2 => {
+            let octets: [u8; 4] = address.try_into().ok()?;
+            (Ipv4Addr::from(octets).to_string(), 32)
+        }
+        3 => {
+            let octets: [u8; 16] = address.try_into().ok()?;
+            (Ipv6Addr::from(octets).to_string(), 128)
+        }
+        _ => return None,
+    };
+    if is_cidr || bits != max_bits {
+        Some(format!("{text}/{bits}"))
+    } else {
+        Some(text)
+    }
+}
+
+/// Arrays are dimensions and lower bounds followed by length-prefixed elements, shown in
+/// Postgres's `{a,b,"c d"}` form with nested braces for each extra dimension.
+fn decode_array(element: &Type, raw: &[u8]) -> Option<String> {
+    let mut r = Reader(raw);
+    let ndim = r.i32()? as usize;
+    let _has_nulls = r.i32()?;
+    let _element_oid = r.i32()?;
+    let mut dims = Vec::with_capacity(ndim);
+    for _ in 0..ndim {
+        dims.push(r.i32()? as usize);
+        let _lower_bound = r.i32()?;
+    }
+    if dims.is_empty() {
+        return Some("{}".to_string());
+    }
+    let total: usize = dims.iter().product();
+    let mut values = Vec::with_capacity(total);
+    for _ in 0..total {
+        let len = r.i32()?;
+        if len < 0 {
+            values.push("NULL".to_string());
+            continue;
         }
+        let text = decode_text(element, r.take(len as usize)?)?;
+        values.push(quote_array_element(&text));
     }
-    return Box::new(map);
+    Some(nest_array(&dims, &values))
 }
 
-pub fn json_get_first_object(json_blob: &Value) -> Option<Value> {
-    let mut rv: Option<Value> = None;
-    if let Some((_key, value)) = json_blob.as_object().unwrap().iter().next() {
-        rv = Some(value.clone());
+pub fn quote_array_element(text: &str) -> String {
+    let plain = !text.is_empty()
+        && !text.eq_ignore_ascii_case("null")
+        && !text
+            .chars()
+            .any(|c| matches!(c, '{' | '}' | ',' | '"' | '\\') || c.is_whitespace());
+    if plain {
+        return text.to_string();
     }
-    return rv;
-}
\ No newline at end of file
+    let escaped = text.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{escaped}\"")
+}
+
+fn nest_array(dims: &[usize], values: &[String]) -> String {
+    if dims.len() <= 1 {
+        return format!("{{{}}}", values.join(","));
+    }
+    let chunk = values.len() / dims[0].max(1);
+    let inner: Vec<String> = values
+        .chunks(chunk.max(1))
+        .map(|c| nest_array(&dims[1..], c))
+        .collect();
+    format!("{{{}}}", inner.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numeric(weight: i16, sign: u16, dscale: i16, digits: &[i16]) -> Vec<u8> {
+        let mut raw = Vec::new();
+        raw.extend((digits.len() as i16).to_be_bytes());
+        raw.extend(weight.to_be_bytes());
+        raw.extend(sign.to_be_bytes());
+        raw.extend(dscale.to_be_bytes());
+        for d in digits {
+            raw.extend(d.to_be_bytes());
+        }
+        raw
+    }
+
+    #[test]
+    fn numerics_keep_their_scale() {
+        let text = |raw: Vec<u8>| decode_text(&Type::NUMERIC, &raw).unwrap();
+        assert_eq!(text(numeric(1, 0, 4, &[1234, 5678, 12])), "12345678.0012");
+        assert_eq!(text(numeric(-2, 0x4000, 6, &[1000])), "-0.000010");
+        assert_eq!(text(numeric(5, 0, 0, &[1])), "100000000000000000000");
+        assert_eq!(text(numeric(0, 0, 2, &[])), "0.00");
+        assert_eq!(text(numeric(0, 0xC000, 0, &[])), "NaN");
+    }
+
+    #[test]
+    fn intervals_read_like_postgres() {
+        assert_eq!(
+            decode_interval(14, 3, 14_706_500_000),
+            "1 year 2 mons 3 days 04:05:06.5"
+        );
+        assert_eq!(decode_interval(0, -1, -2_000_000), "-1 days -00:00:02");
+        assert_eq!(decode_interval(0, 0, 0), "00:00:00");
+    }
+
+    #[test]
+    fn dates_and_times() {
+        assert_eq!(decode_date(8825).unwrap(), "2024-02-29");
+        assert_eq!(decode_date(i32::MAX).unwrap(), "infinity");
+        assert_eq!(
+            decode_timestamp(737_662_756_500_000).unwrap(),
+            "2023-05-17 18:19:16.5"
+        );
+        assert_eq!(decode_timestamp(-1).unwrap(), "1999-12-31 23:59:59.999999");
+        assert_eq!(decode_offset(19_800), "+05:30");
+        assert_eq!(decode_offset(-3600), "-01");
+    }
+
+    #[test]
+    fn floats_keep_their_own_precision() {
+        let float4 = decode_text(&Type::FLOAT4, &0.1f32.to_be_bytes()).unwrap();
+        assert_eq!(float4, "0.1");
+        let float8 = decode_text(&Type::FLOAT8, &0.1f64.to_be_bytes()).unwrap();
+        assert_eq!(float8, "0.1");
+        let infinite = decode_text(&Type::FLOAT4, &f32::NEG_INFINITY.to_be_bytes()).unwrap();
+        assert_eq!(infinite, "-Infinity");
+    }
+
+    #[test]
+    fn addresses_money_and_bytes() {
+        assert_eq!(
+            decode_inet(&[2, 8, 0, 4, 10, 1, 2, 3]).unwrap(),
+            "10.1.2.3/8"
+        );
+        assert_eq!(
+            decode_inet(&[2, 32, 0, 4, 192, 168, 1, 5]).unwrap(),
+            "192.168.1.5"
+        );
+        assert_eq!(
+            decode_inet(&[2, 32, 1, 4, 10, 0, 0, 0]).unwrap(),
+            "10.0.0.0/32"
+        );
+        assert_eq!(decode_money(123_456_789), "$1,234,567.89");
+        assert_eq!(decode_money(-500), "-$5.00");
+        assert_eq!(decode_bytea(&[0xde, 0xad]), "\\xdead");
+    }
+
+    #[test]
+    fn arrays_nest_and_quote() {
+        let mut raw = Vec::new();
+        for n in [2i32, 1, 25, 2, 1, 2, 1] {
+            raw.extend(n.to_be_bytes());
+        }
+        for element in ["a", "b c", "", "x\"y"] {
+            raw.extend((element.len() as i32).to_be_bytes());
+            raw.extend(element.as_bytes());
+        }
+        let text = decode_text(&Type::TEXT_ARRAY, &raw).unwrap();
+        assert_eq!(text, r#"{{a,"b c"},{"","x\"y"}}"#);
+    }
+
+    #[test]
+    fn unknown_types_need_the_text_fallback() {
+        assert!(can_decode(&Type::INT4_ARRAY));
+        assert!(can_decode(&Type::JSONB));
+        assert!(!can_decode(&Type::TS_VECTOR));
+        assert!(!can_decode(&Type::REGCLASS));
+    }
+}
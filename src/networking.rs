@@ -1,56 +1,1040 @@
-use std::error::Error;
+use crate::models::*;
 use crate::NeonSession;
-use std::collections::HashMap;
-
-pub async fn do_http_get(url: String, neon_config: &NeonSession) -> Result<String, Box<dyn Error>> {
-    let client = reqwest::Client::builder().build()?;
-    let body = client
-    .get(url)
-    .header("Authorization", format!("Bearer {}", &neon_config.neon_api_key))
-    .header("Accept", "application/json")
-    .send().await;
-    let response = body.expect("Failed to execute request.");
-    let body = response.text().await;
-    Ok(body.unwrap())
-}
-
-pub async fn do_http_post(url:String, postbody: &HashMap<String,String>, neon_config: &NeonSession) -> Result<String, Box<dyn Error>> {
-    let client = reqwest::Client::builder().build()?;
-    let body = client
-    .post(url)
-    .json(&postbody)
-    .header("Authorization", format!("Bearer {}", &neon_config.neon_api_key))
-    .header("Accept", "application/json")
-    .send().await;
-    let response = body.expect("Failed to execute request.");
-    let body = response.text().await;
-    Ok(body.unwrap())
-}
-
-pub async fn do_http_post_text(url:String, postbody: &String, neon_config: &NeonSession) -> Result<String, Box<dyn Error>> {
-    let client = reqwest::Client::builder().build()?;
-    let body = client
-    .post(url)
-    .body(postbody.clone())
-    .header("Authorization", format!("Bearer {}", &neon_config.neon_api_key))
-    .header("Accept", "application/json")
-    .header("Content-Type", "application/json")
-    .send().await;
-    let response = body.expect("Failed to execute request.");
-    let body = response.text().await;
-    Ok(body.unwrap())
-}
-
-
-pub async fn do_http_delete(url: String, neon_config: &NeonSession) -> Result<String, Box<dyn Error>> {
-    let client = reqwest::Client::builder().build()?;
-    println!("url: {}", url);
-    let body = client
-    .delete(url)
-    .header("Authorization", format!("Bearer {}", &neon_config.neon_api_key))
-    .header("Accept", "application/json")
-    .send().await;
-    let response = body.expect("Failed to execute request.");
-    let body = response.text().await;
-    Ok(body.unwrap())
-}
\ No newline at end of file
+use reqwest::{header, Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+pub const NEON_BASE_URL: &str = "https://console.neon.tech/api/v2";
+
+/// How often `--wait` polls an operation that hasn't finished yet.
+const OPERATION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The `{"code": ..., "message": ...}` body Neon sends with a failed request.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct ApiErrorBody {
+    #[serde(default)]
+    pub code: Option<String>,
+    #[serde(default)]
+    pub message: String,
+}
+
+impl ApiErrorBody {
+    fn parse(status: StatusCode, text: &str) -> ApiErrorBody {
+        let mut body: ApiErrorBody = serde_json::from_str(text).unwrap_or_default();
+        if body.message.is_empty() {
+            body.message = match text.trim() {
+                "" => status.canonical_reason().unwrap_or("").to_string(),
+                t => t.to_string(),
+            };
+        }
+        body
+    }
+}
+
+impl fmt::Display for ApiErrorBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.code {
+            Some(code) if !code.is_empty() => write!(f, "{} [{code}]", self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum NeonApiError {
+    /// 401: the API key is missing, wrong or revoked.
+    Unauthorized(ApiErrorBody),
+    /// 404: the project, branch, endpoint, etc. doesn't exist.
+    NotFound(ApiErrorBody),
+    /// 409: the request conflicts with what's there, e.g. a name that's already taken.
+    Conflict(ApiErrorBody),
+    /// 423: another operation is still running on the project.
+    Locked(ApiErrorBody),
+    /// 429: too many requests.
+    RateLimited(ApiErrorBody),
+    /// 5xx: something went wrong on Neon's side.
+    Server(u16, ApiErrorBody),
+    /// Any other unsuccessful status.
+    Status(u16, ApiErrorBody),
+    /// The request never got a response.
+    Transport(reqwest::Error),
+    /// The response wasn't the JSON we expected.
+    Decode(serde_json::Error),
+    /// `--wait` saw an operation end in failure.
+    OperationFailed(Box<Operation>),
+    /// `--wait` gave up on an operation that was still running.
+    WaitTimeout(Box<Operation>, Duration),
+    /// `--query` couldn't be applied to the response.
+    Query(String),
+    /// The options given don't make sense for the action.
+    Usage(String),
+}
+
+impl NeonApiError {
+    pub fn from_response(status: StatusCode, text: &str) -> NeonApiError {
+        let body = ApiErrorBody::parse(status, text);
+        match status.as_u16() {
+            401 => NeonApiError::Unauthorized(body),
+            404 => NeonApiError::NotFound(body),
+            409 => NeonApiError::Conflict(body),
+            423 => NeonApiError::Locked(body),
+            429 => NeonApiError::RateLimited(body),
+            s if status.is_server_error() => NeonApiError::Server(s, body),
+            s => NeonApiError::Status(s, body),
+        }
+    }
+}
+
+impl fmt::Display for NeonApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NeonApiError::Unauthorized(b) => {
+                write!(f, "Unauthorized (401): {b}. Check your Neon API key.")
+            }
+            NeonApiError::NotFound(b) => write!(f, "Not found (404): {b}"),
+            NeonApiError::Conflict(b) => write!(f, "Conflict (409): {b}"),
+            NeonApiError::Locked(b) => write!(
+                f,
+                "Project is locked by another operation: {b}. Try again once it finishes."
+            ),
+            NeonApiError::RateLimited(b) => write!(f, "Rate limited (429): {b}"),
+            NeonApiError::Server(s, b) => write!(f, "Neon server error ({s}): {b}"),
+            NeonApiError::Status(s, b) => write!(f, "Request failed ({s}): {b}"),
+            NeonApiError::Transport(e) => write!(f, "Could not reach the Neon API: {e}"),
+            NeonApiError::Decode(e) => write!(f, "Unexpected response from the Neon API: {e}"),
+            NeonApiError::OperationFailed(op) => write!(
+                f,
+                "Operation {} ({}) {}: {}",
+                op.id,
+                op.action,
+                op.status,
+                op.error.as_deref().unwrap_or("no error message")
+            ),
+            NeonApiError::WaitTimeout(op, timeout) => write!(
+                f,
+                "Timed out after {}s waiting for operation {} ({}), which is still {}.",
+                timeout.as_secs(),
+                op.id,
+                op.action,
+                op.status
+            ),
+            NeonApiError::Query(message) | NeonApiError::Usage(message) => {
+                write!(f, "{message}")
+            }
+        }
+    }
+}
+
+impl NeonApiError {
+    /// Whether trying the same request again later might succeed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            NeonApiError::Locked(_) | NeonApiError::RateLimited(_) | NeonApiError::Server(_, _) => {
+                true
+            }
+            NeonApiError::Transport(e) => e.is_connect() || e.is_timeout(),
+            _ => false,
+        }
+    }
+}
+
+impl std::error::Error for NeonApiError {}
+
+impl From<reqwest::Error> for NeonApiError {
+    fn from(e: reqwest::Error) -> Self {
+        NeonApiError::Transport(e)
+    }
+}
+
+impl From<serde_json::Error> for NeonApiError {
+    fn from(e: serde_json::Error) -> Self {
+        NeonApiError::Decode(e)
+    }
+}
+
+/// Turns a typed response back into JSON for printing.
+pub fn as_json<T: Serialize>(t: T) -> Result<Value, NeonApiError> {
+    Ok(serde_json::to_value(t)?)
+}
+
+/// A response ready to print: its JSON and where its items are for the row formats.
+#[derive(Debug)]
+pub struct Response {
+    pub body: Value,
+    pub items: Option<&'static str>,
+    /// The columns `-f table` shows by default.
+    pub columns: &'static [&'static str],
+}
+
+impl Response {
+    /// The objects the row formats print: the items list, or the single item as a list of
+    /// one.
+    pub fn rows(&self) -> Vec<Value> {
+        let items = match self.items {
+            Some(key) => &self.body[key],
+            None => &self.body,
+        };
+        match items {
+            Value::Array(rows) => rows.clone(),
+            Value::Null => Vec::new(),
+            item => vec![item.clone()],
+        }
+    }
+}
+
+/// Turns a typed response into a Response, keeping where its type says its items are.
+pub fn as_response<T: Serialize + Listing>(t: T) -> Result<Response, NeonApiError> {
+    Ok(Response {
+        body: as_json(t)?,
+        items: T::ITEMS,
+        columns: T::COLUMNS,
+    })
+}
+
+/// Which page of a paginated list to ask for.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Page {
+    /// The `pagination.cursor` of the page before; none starts at the beginning.
+    pub cursor: Option<String>,
+    /// How many items to ask for; none leaves it to the API.
+    pub limit: Option<u32>,
+}
+
+impl Page {
+    /// The query string asking for this page, empty when it has no cursor or limit.
+    fn query(&self) -> String {
+        let mut params = Vec::new();
+        if let Some(cursor) = &self.cursor {
+            params.push(format!("cursor={}", percent_encode(cursor)));
+        }
+        if let Some(limit) = self.limit {
+            params.push(format!("limit={limit}"));
+        }
+        if params.is_empty() {
+            String::new()
+        } else {
+            format!("?{}", params.join("&"))
+        }
+    }
+}
+
+/// Percent-encodes everything but unreserved characters, for query strings and the parts of
+/// a connection URI, since cursors, names and passwords can hold anything.
+pub fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// The path of a role.  Role and database names can hold anything, `/` and `?` included.
+fn role_path(project: &str, branch: &str, role: &str) -> String {
+    format!(
+        "/projects/{project}/branches/{branch}/roles/{}",
+        percent_encode(role)
+    )
+}
+
+fn database_path(project: &str, branch: &str, database: &str) -> String {
+    format!(
+        "/projects/{project}/branches/{branch}/databases/{}",
+        percent_encode(database)
+    )
+}
+
+/// How much of a paginated list to fetch: `--cursor`, `--page-size`, `--all` and
+/// `--max-items`.
+#[derive(Debug, Default, Clone)]
+pub struct Paging {
+    pub first: Page,
+    /// Follow the cursor to the end of the list, rather than fetching one page.
+    pub all: bool,
+    pub max_items: Option<usize>,
+}
+
+/// Fetches a paginated list with `fetch`, following each response's `pagination.cursor`.
+/// With `paging.all` it keeps going until a page comes back empty or short, otherwise it
+/// stops after one page; either way it stops at `paging.max_items`.  The first response is
+/// returned holding every page's items and the last page's pagination.  When `stream` is
+/// given, each page's items go to it as they arrive and aren't kept.
+pub async fn paginate<F, Fut>(
+    paging: &Paging,
+    mut fetch: F,
+    mut stream: Option<&mut dyn FnMut(Vec<Value>)>,
+) -> Result<Response, NeonApiError>
+where
+    F: FnMut(Page) -> Fut,
+    Fut: Future<Output = Result<Response, NeonApiError>>,
+{
+    let mut page = paging.first.clone();
+    let mut merged: Option<Response> = None;
+    let mut fetched: usize = 0;
+    loop {
+        let remaining = paging.max_items.map(|max| max - fetched);
+        if let (Some(limit), Some(remaining)) = (page.limit, remaining) {
+            page.limit = Some(limit.min(u32::try_from(remaining).unwrap_or(u32::MAX)));
+        }
+        let mut response = fetch(page.clone()).await?;
+        let Some(key) = response.items else {
+            return Ok(response);
+        };
+        let mut items = match response.body[key].take() {
+            Value::Array(items) => items,
+            _ => Vec::new(),
+        };
+        let received = items.len();
+        if let Some(remaining) = remaining {
+            items.truncate(remaining);
+        }
+        fetched += items.len();
+        let cursor = response.body["pagination"]["cursor"]
+            .as_str()
+            .filter(|c| !c.is_empty())
+            .map(str::to_string);
+        let done = !paging.all
+            || received == 0
+            || page.limit.is_some_and(|limit| received < limit as usize)
+            || paging.max_items.is_some_and(|max| fetched >= max)
+            || cursor.is_none()
+            || cursor == page.cursor;
+        if let Some(each_page) = stream.as_mut() {
+            each_page(std::mem::take(&mut items));
+        }
+        let so_far = match merged.take() {
+            None => {
+                response.body[key] = Value::Array(items);
+                response
+            }
+            Some(mut so_far) => {
+                if let Some(all) = so_far.body[key].as_array_mut() {
+                    all.extend(items);
+                }
+                so_far.body["pagination"] = response.body["pagination"].take();
+                so_far
+            }
+        };
+        if done {
+            return Ok(so_far);
+        }
+        merged = Some(so_far);
+        page.cursor = cursor;
+    }
+}
+
+/// How failed requests are retried.  Only idempotent requests (GET, PUT, DELETE) are
+/// retried unless `retry_writes` is set, since a POST or PATCH that timed out may already
+/// have been applied.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub retry_writes: bool,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            retry_writes: false,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn applies_to(&self, method: &Method) -> bool {
+        self.retry_writes || matches!(*method, Method::GET | Method::PUT | Method::DELETE)
+    }
+
+    /// Exponential backoff with full jitter: a random delay up to `base_delay * 2^attempt`,
+    /// capped at `max_delay`.  A Retry-After from the server takes precedence, though it
+    /// is held to `max_delay` too.
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(d) = retry_after {
+            return d.min(self.max_delay);
+        }
+        let ceiling = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        ceiling.mul_f64(random_fraction())
+    }
+}
+
+/// A number in [0, 1).  Good enough for jitter without pulling in a random number crate.
+fn random_fraction() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Reads a Retry-After header, which is either a number of seconds or an HTTP date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let when = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = when.signed_duration_since(chrono::Utc::now());
+    Some(wait.to_std().unwrap_or(Duration::ZERO))
+}
+
+/// The configured API base URL without a trailing slash, or Neon's public API if unset.
+fn api_base_url(configured: &str) -> String {
+    match configured.trim_end_matches('/') {
+        "" => NEON_BASE_URL.to_string(),
+        url => url.to_string(),
+    }
+}
+
+/// A client for the Neon v2 API sharing one connection pool across requests.
+pub struct NeonApiClient {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: String,
+    retry: RetryPolicy,
+    wait: Option<Duration>,
+}
+
+impl NeonApiClient {
+    pub fn new(neon_config: &NeonSession) -> Result<NeonApiClient, NeonApiError> {
+        Ok(NeonApiClient {
+            http: reqwest::Client::builder().build()?,
+            base_url: api_base_url(&neon_config.api_url),
+            api_key: neon_config.neon_api_key.clone(),
+            retry: neon_config.retry.clone(),
+            wait: neon_config.wait,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+
+    /// Sends a request and returns the JSON body, or the API's error for any non-2xx status.
+    /// Busy (423), rate limited (429) and server (5xx) responses are retried per the policy.
+    pub async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<Value, NeonApiError> {
+        let retry_allowed = self.retry.applies_to(&method);
+        let mut attempt: u32 = 0;
+        loop {
+            match self.send_once(method.clone(), path, body).await {
+                Err((e, retry_after))
+                    if retry_allowed && attempt < self.retry.max_retries && e.is_retryable() =>
+                {
+                    let delay = self.retry.delay(attempt, retry_after);
+                    attempt += 1;
+                    eprintln!(
+                        "{e}\nRetrying in {:.1}s (attempt {attempt} of {}).",
+                        delay.as_secs_f64(),
+                        self.retry.max_retries
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                r => return r.map_err(|(e, _)| e),
+            }
+        }
+    }
+
+    /// One attempt at a request.  Errors carry the server's Retry-After, if it sent one.
+    async fn send_once(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<Value, (NeonApiError, Option<Duration>)> {
+        let mut request = self
+            .http
+            .request(method, self.url(path))
+            .bearer_auth(&self.api_key)
+            .header(header::ACCEPT, "application/json");
+        if let Some(b) = body {
+            request = request.json(b);
+        }
+        let response = request.send().await.map_err(|e| (e.into(), None))?;
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+        let text = response.text().await.map_err(|e| (e.into(), None))?;
+        if !status.is_success() {
+            return Err((NeonApiError::from_response(status, &text), retry_after));
+        }
+        if text.trim().is_empty() {
+            return Ok(Value::Null);
+        }
+        serde_json::from_str(&text).map_err(|e| (e.into(), None))
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<T, NeonApiError> {
+        let value = self.send(method, path, body).await?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, NeonApiError> {
+        self.call(Method::GET, path, None).await
+    }
+
+    pub async fn post<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &Value,
+    ) -> Result<T, NeonApiError> {
+        self.call(Method::POST, path, Some(body)).await
+    }
+
+    pub async fn patch<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &Value,
+    ) -> Result<T, NeonApiError> {
+        self.call(Method::PATCH, path, Some(body)).await
+    }
+
+    pub async fn delete<T: DeserializeOwned>(&self, path: &str) -> Result<T, NeonApiError> {
+        self.call(Method::DELETE, path, None).await
+    }
+
+    pub async fn list_api_keys(&self) -> Result<Vec<ApiKey>, NeonApiError> {
+        self.get("/api_keys").await
+    }
+
+    pub async fn create_api_key(&self, name: &str) -> Result<ApiKeyCreated, NeonApiError> {
+        self.post("/api_keys", &json!({ "key_name": name })).await
+    }
+
+    pub async fn revoke_api_key(&self, key_id: &str) -> Result<ApiKey, NeonApiError> {
+        self.delete(&format!("/api_keys/{key_id}")).await
+    }
+
+    pub async fn list_projects(&self, page: &Page) -> Result<ProjectsResponse, NeonApiError> {
+        self.get(&format!("/projects{}", page.query())).await
+    }
+
+    pub async fn get_project(&self, project: &str) -> Result<ProjectResponse, NeonApiError> {
+        self.get(&format!("/projects/{project}")).await
+    }
+
+    pub async fn delete_project(&self, project: &str) -> Result<ProjectResponse, NeonApiError> {
+        self.delete(&format!("/projects/{project}")).await
+    }
+
+    pub async fn create_project(
+        &self,
+        changes: &ProjectChanges,
+    ) -> Result<ProjectCreated, NeonApiError> {
+        self.post("/projects", &changes.to_request()).await
+    }
+
+    pub async fn update_project(
+        &self,
+        project: &str,
+        changes: &ProjectChanges,
+    ) -> Result<ProjectUpdated, NeonApiError> {
+        self.patch(&format!("/projects/{project}"), &changes.to_request())
+            .await
+    }
+
+    /// A connection URI for each database on the project's default branch, as its owner,
+    /// the way creating a project returns them.  Older responses only mark that branch
+    /// with the deprecated `primary` flag.
+    pub async fn connection_uris(&self, project: &str) -> Result<Vec<ConnectionUri>, NeonApiError> {
+        let branches = self.list_branches(project).await?.branches;
+        let Some(default_branch) = branches
+            .iter()
+            .find(|b| b.default == Some(true))
+            .or_else(|| branches.iter().find(|b| b.primary == Some(true)))
+        else {
+            return Ok(Vec::new());
+        };
+        let mut uris = Vec::new();
+        for database in self
+            .list_databases(project, &default_branch.id)
+            .await?
+            .databases
+        {
+            uris.push(
+                self.connection_uri(
+                    project,
+                    &default_branch.id,
+                    &database.owner_name,
+                    &database.name,
+                    None,
+                    false,
+                )
+                .await?,
+            );
+        }
+        Ok(uris)
+    }
+
+    /// The API's connection URI for `role` on `database`, with the role's password filled
+    /// in.  Without `endpoint` it goes through the branch's read-write endpoint; `pooled`
+    /// goes through the endpoint's PgBouncer.
+    pub async fn connection_uri(
+        &self,
+        project: &str,
+        branch: &str,
+        role: &str,
+        database: &str,
+        endpoint: Option<&str>,
+        pooled: bool,
+    ) -> Result<ConnectionUri, NeonApiError> {
+        let mut path = format!(
+            "/projects/{project}/connection_uri?branch_id={}&database_name={}&role_name={}",
+            percent_encode(branch),
+            percent_encode(database),
+            percent_encode(role)
+        );
+        if let Some(endpoint) = endpoint {
+            path.push_str(&format!("&endpoint_id={}", percent_encode(endpoint)));
+        }
+        if pooled {
+            path.push_str("&pooled=true");
+        }
+        let response: ConnectionUriResponse = self.get(&path).await?;
+        Ok(ConnectionUri {
+            connection_uri: response.uri,
+            ..Default::default()
+        })
+    }
+
+    pub async fn list_branches(&self, project: &str) -> Result<BranchesResponse, NeonApiError> {
+        self.get(&format!("/projects/{project}/branches")).await
+    }
+
+    pub async fn create_branch(
+        &self,
+        project: &str,
+        new_branch: &NewBranch,
+    ) -> Result<BranchCreated, NeonApiError> {
+        self.post(
+            &format!("/projects/{project}/branches"),
+            &new_branch.to_request(),
+        )
+        .await
+    }
+
+    pub async fn get_branch(
+        &self,
+        project: &str,
+        branch: &str,
+    ) -> Result<BranchResponse, NeonApiError> {
+        self.get(&format!("/projects/{project}/branches/{branch}"))
+            .await
+    }
+
+    pub async fn delete_branch(
+        &self,
+        project: &str,
+        branch: &str,
+    ) -> Result<BranchOperations, NeonApiError> {
+        self.delete(&format!("/projects/{project}/branches/{branch}"))
+            .await
+    }
+
+    pub async fn list_branch_endpoints(
+        &self,
+        project: &str,
+        branch: &str,
+    ) -> Result<EndpointsResponse, NeonApiError> {
+        self.get(&format!("/projects/{project}/branches/{branch}/endpoints"))
+            .await
+    }
+
+    pub async fn list_roles(
+        &self,
+        project: &str,
+        branch: &str,
+    ) -> Result<RolesResponse, NeonApiError> {
+        self.get(&format!("/projects/{project}/branches/{branch}/roles"))
+            .await
+    }
+
+    pub async fn get_role(
+        &self,
+        project: &str,
+        branch: &str,
+        role: &str,
+    ) -> Result<RoleResponse, NeonApiError> {
+        self.get(&role_path(project, branch, role)).await
+    }
+
+    pub async fn delete_role(
+        &self,
+        project: &str,
+        branch: &str,
+        role: &str,
+    ) -> Result<RoleOperations, NeonApiError> {
+        self.delete(&role_path(project, branch, role)).await
+    }
+
+    pub async fn create_role(
+        &self,
+        project: &str,
+        branch: &str,
+        role: &str,
+    ) -> Result<RoleCreated, NeonApiError> {
+        self.post(
+            &format!("/projects/{project}/branches/{branch}/roles"),
+            &json!({ "role": { "name": role } }),
+        )
+        .await
+    }
+
+    pub async fn reset_role_password(
+        &self,
+        project: &str,
+        branch: &str,
+        role: &str,
+    ) -> Result<RoleCreated, NeonApiError> {
+        self.post(
+            &format!("{}/reset_password", role_path(project, branch, role)),
+            &json!({}),
+        )
+        .await
+    }
+
+    pub async fn reveal_role_password(
+        &self,
+        project: &str,
+        branch: &str,
+        role: &str,
+    ) -> Result<RolePassword, NeonApiError> {
+        self.get(&format!(
+            "{}/reveal_password",
+            role_path(project, branch, role)
+        ))
+        .await
+    }
+
+    pub async fn list_databases(
+        &self,
+        project: &str,
+        branch: &str,
+    ) -> Result<DatabasesResponse, NeonApiError> {
+        self.get(&format!("/projects/{project}/branches/{branch}/databases"))
+            .await
+    }
+
+    pub async fn get_database(
+        &self,
+        project: &str,
+        branch: &str,
+        database: &str,
+    ) -> Result<DatabaseResponse, NeonApiError> {
+        self.get(&database_path(project, branch, database)).await
+    }
+
+    pub async fn create_database(
+        &self,
+        project: &str,
+        branch: &str,
+        database: &str,
+        owner: &str,
+    ) -> Result<DatabaseOperations, NeonApiError> {
+        self.post(
+            &format!("/projects/{project}/branches/{branch}/databases"),
+            &json!({ "database": { "name": database, "owner_name": owner } }),
+        )
+        .await
+    }
+
+    /// Renames `database` and/or gives it to another owner; what is None stays the same.
+    pub async fn update_database(
+        &self,
+        project: &str,
+        branch: &str,
+        database: &str,
+        new_name: Option<&str>,
+        owner: Option<&str>,
+    ) -> Result<DatabaseOperations, NeonApiError> {
+        let mut changes = serde_json::Map::new();
+        if let Some(name) = new_name {
+            changes.insert("name".to_string(), json!(name));
+        }
+        if let Some(owner) = owner {
+            changes.insert("owner_name".to_string(), json!(owner));
+        }
+        self.patch(
+            &database_path(project, branch, database),
+            &json!({ "database": changes }),
+        )
+        .await
+    }
+
+    pub async fn delete_database(
+        &self,
+        project: &str,
+        branch: &str,
+        database: &str,
+    ) -> Result<DatabaseOperations, NeonApiError> {
+        self.delete(&database_path(project, branch, database)).await
+    }
+
+    pub async fn create_endpoint(
+        &self,
+        project: &str,
+        endpoint: &Value,
+    ) -> Result<EndpointOperations, NeonApiError> {
+        self.post(
+            &format!("/projects/{project}/endpoints"),
+            &json!({ "endpoint": endpoint }),
+        )
+        .await
+    }
+
+    pub async fn list_endpoints(&self, project: &str) -> Result<EndpointsResponse, NeonApiError> {
+        self.get(&format!("/projects/{project}/endpoints")).await
+    }
+
+    pub async fn get_endpoint(
+        &self,
+        project: &str,
+        endpoint: &str,
+    ) -> Result<EndpointResponse, NeonApiError> {
+        self.get(&format!("/projects/{project}/endpoints/{endpoint}"))
+            .await
+    }
+
+    pub async fn delete_endpoint(
+        &self,
+        project: &str,
+        endpoint: &str,
+    ) -> Result<EndpointOperations, NeonApiError> {
+        self.delete(&format!("/projects/{project}/endpoints/{endpoint}"))
+            .await
+    }
+
+    pub async fn update_endpoint(
+        &self,
+        project: &str,
+        endpoint: &str,
+        changes: &EndpointChanges,
+    ) -> Result<EndpointOperations, NeonApiError> {
+        self.patch(
+            &format!("/projects/{project}/endpoints/{endpoint}"),
+            &json!({ "endpoint": changes.to_fields() }),
+        )
+        .await
+    }
+
+    pub async fn start_endpoint(
+        &self,
+        project: &str,
+        endpoint: &str,
+    ) -> Result<EndpointOperations, NeonApiError> {
+        self.post(
+            &format!("/projects/{project}/endpoints/{endpoint}/start"),
+            &json!({}),
+        )
+        .await
+    }
+
+    pub async fn suspend_endpoint(
+        &self,
+        project: &str,
+        endpoint: &str,
+    ) -> Result<EndpointOperations, NeonApiError> {
+        self.post(
+            &format!("/projects/{project}/endpoints/{endpoint}/suspend"),
+            &json!({}),
+        )
+        .await
+    }
+
+    pub async fn list_operations(
+        &self,
+        project: &str,
+        page: &Page,
+    ) -> Result<OperationsResponse, NeonApiError> {
+        self.get(&format!("/projects/{project}/operations{}", page.query()))
+            .await
+    }
+
+    pub async fn get_operation(
+        &self,
+        project: &str,
+        operation: &str,
+    ) -> Result<OperationResponse, NeonApiError> {
+        self.get(&format!("/projects/{project}/operations/{operation}"))
+            .await
+    }
+
+    /// With `--wait`, blocks until every operation in a mutating call's response has
+    /// finished, so the next command doesn't hit 423 Locked.  Passes the response through.
+    pub async fn settle(
+        &self,
+        response: Result<Response, NeonApiError>,
+    ) -> Result<Response, NeonApiError> {
+        let response = response?;
+        if let (Some(_), Some(operations)) = (self.wait, response.body.get("operations")) {
+            let operations: Vec<Operation> = serde_json::from_value(operations.clone())?;
+            self.wait_for(&operations).await?;
+        }
+        Ok(response)
+    }
+
+    /// With `--wait`, blocks until `operations` have finished; otherwise returns at once.
+    /// `settle` goes through here too, for the calls whose typed response is used directly.
+    pub async fn wait_for(&self, operations: &[Operation]) -> Result<(), NeonApiError> {
+        match self.wait {
+            Some(timeout) => self.wait_for_operations(operations.to_vec(), timeout).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Polls each operation until it finishes or fails, reporting status changes on stderr.
+    /// `timeout` bounds the whole wait, not each operation.
+    async fn wait_for_operations(
+        &self,
+        operations: Vec<Operation>,
+        timeout: Duration,
+    ) -> Result<(), NeonApiError> {
+        let deadline = Instant::now() + timeout;
+        for mut op in operations {
+            let mut reported = String::new();
+            loop {
+                if op.status != reported {
+                    eprintln!("Operation {} ({}): {}", op.id, op.action, op.status);
+                    reported = op.status.clone();
+                }
+                match op.status.as_str() {
+                    "finished" | "skipped" => break,
+                    "failed" | "error" | "cancelled" => {
+                        return Err(NeonApiError::OperationFailed(Box::new(op)))
+                    }
+                    _ => {}
+                }
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(NeonApiError::WaitTimeout(Box::new(op), timeout));
+                }
+                tokio::time::sleep(OPERATION_POLL_INTERVAL.min(deadline - now)).await;
+                op = self.get_operation(&op.project_id, &op.id).await?.operation;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn consumption(&self, page: &Page) -> Result<ConsumptionResponse, NeonApiError> {
+        self.get(&format!("/consumption/projects{}", page.query()))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_idempotent_methods_retry_by_default() {
+        let policy = RetryPolicy::default();
+        assert!(policy.applies_to(&Method::GET));
+        assert!(policy.applies_to(&Method::DELETE));
+        assert!(!policy.applies_to(&Method::POST));
+        assert!(!policy.applies_to(&Method::PATCH));
+
+        let writes = RetryPolicy {
+            retry_writes: true,
+            ..Default::default()
+        };
+        assert!(writes.applies_to(&Method::POST));
+    }
+
+    #[test]
+    fn backoff_grows_and_is_capped() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            assert!(policy.delay(0, None) <= Duration::from_secs(1));
+            assert!(policy.delay(2, None) <= Duration::from_secs(4));
+            assert!(policy.delay(10, None) <= Duration::from_secs(5));
+        }
+    }
+
+    #[test]
+    fn retry_after_wins_over_backoff() {
+        let policy = RetryPolicy::default();
+        let wait = Some(Duration::from_secs(7));
+        assert_eq!(policy.delay(0, wait), Duration::from_secs(7));
+
+        let hour = Some(Duration::from_secs(3600));
+        assert_eq!(policy.delay(0, hour), policy.max_delay);
+    }
+
+    #[test]
+    fn retry_after_accepts_seconds_and_dates() {
+        assert_eq!(parse_retry_after(" 12 "), Some(Duration::from_secs(12)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn locked_is_retried_but_conflict_is_not() {
+        let busy = NeonApiError::from_response(StatusCode::LOCKED, "{}");
+        let conflict = NeonApiError::from_response(StatusCode::CONFLICT, "{}");
+        let missing = NeonApiError::from_response(StatusCode::NOT_FOUND, "{}");
+        let unavailable = NeonApiError::from_response(StatusCode::SERVICE_UNAVAILABLE, "");
+        assert!(busy.is_retryable());
+        assert!(!conflict.is_retryable());
+        assert!(!missing.is_retryable());
+        assert!(matches!(conflict, NeonApiError::Conflict(_)));
+        assert!(conflict.to_string().starts_with("Conflict (409)"));
+        assert!(unavailable.is_retryable());
+    }
+
+    #[test]
+    fn pages_ask_with_a_query_string() {
+        assert_eq!(Page::default().query(), "");
+        let page = Page {
+            cursor: Some("2023-05-17T18:19:16Z/p 1".to_string()),
+            limit: Some(50),
+        };
+        assert_eq!(
+            page.query(),
+            "?cursor=2023-05-17T18%3A19%3A16Z%2Fp%201&limit=50"
+        );
+    }
+
+    #[test]
+    fn role_and_database_names_are_escaped_in_paths() {
+        assert_eq!(
+            role_path("p", "br", "a/b c"),
+            "/projects/p/branches/br/roles/a%2Fb%20c"
+        );
+        assert_eq!(
+            database_path("p", "br", "50%?#"),
+            "/projects/p/branches/br/databases/50%25%3F%23"
+        );
+    }
+}
@@ -0,0 +1,559 @@
+//! Renders rows in the formats the commands print: `query` results and API responses both
+//! go through here, so every command supports the same formats.
+
+use comfy_table::*;
+use comfy_table::{modifiers::UTF8_ROUND_CORNERS, presets::UTF8_FULL};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use serde_json::Value;
+use std::cmp::Ordering;
+
+pub const FORMATS: [&str; 8] = [
+    "table", "csv", "tsv", "json", "ndjson", "yaml", "markdown", "vertical",
+];
+
+/// What the API commands accept: the row formats, plus `raw` for single values.
+pub const API_FORMATS: [&str; 9] = [
+    "json", "yaml", "table", "csv", "tsv", "ndjson", "markdown", "vertical", "raw",
+];
+
+/// Rows of values under named columns.
+#[derive(Debug, Default, PartialEq)]
+pub struct Rows {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl Rows {
+    /// Rows from JSON objects, such as the items of an API response.  The columns are every
+    /// key any object has; objects without one get null for it.
+    pub fn from_objects(objects: &[Value]) -> Rows {
+        let mut columns: Vec<String> = Vec::new();
+        for object in objects.iter().filter_map(Value::as_object) {
+            for key in object.keys() {
+                if !columns.contains(key) {
+                    columns.push(key.clone());
+                }
+            }
+        }
+        let rows = objects
+            .iter()
+            .map(|object| columns.iter().map(|c| object[c].clone()).collect())
+            .collect();
+        Rows { columns, rows }
+    }
+}
+
+/// The value at a dotted path into nested objects, such as `settings.quota.active_time_seconds`.
+/// Array elements are reached by index, as in `hosts.0`.  Missing fields are null.
+pub fn lookup<'a>(value: &'a Value, path: &str) -> &'a Value {
+    path.split('.').fold(value, |v, key| {
+        match v {
+            Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => v.get(key),
+        }
+        .unwrap_or(&Value::Null)
+    })
+}
+
+/// `--sort-by field[:asc|:desc]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    pub path: String,
+    pub descending: bool,
+}
+
+impl SortKey {
+    pub fn parse(s: &str) -> Result<SortKey, String> {
+        let (path, descending) = match s.rsplit_once(':') {
+            Some((path, "desc")) => (path, true),
+            Some((path, "asc")) => (path, false),
+            Some(_) => return Err("expected field, field:asc or field:desc".to_string()),
+            None => (s, false),
+        };
+        Ok(SortKey {
+            path: path.to_string(),
+            descending,
+        })
+    }
+}
+
+/// `--filter field=value` or `field!=value`, comparing with the value as a table shows it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub path: String,
+    pub value: String,
+    pub negate: bool,
+}
+
+impl Filter {
+    pub fn parse(s: &str) -> Result<Filter, String> {
+        let (path, value) = s
+            .split_once('=')
+            .ok_or("expected field=value or field!=value")?;
+        let (path, negate) = match path.strip_suffix('!') {
+            Some(path) => (path, true),
+            None => (path, false),
+        };
+        Ok(Filter {
+            path: path.to_string(),
+            value: value.to_string(),
+            negate,
+        })
+    }
+
+    fn matches(&self, object: &Value) -> bool {
+        (cell(lookup(object, &self.path)) == self.value) != self.negate
+    }
+}
+
+/// Orders numbers numerically and everything else by its text, with nulls last either way.
+fn compare(a: &Value, b: &Value, descending: bool) -> Ordering {
+    let order = match (a, b) {
+        (Value::Null, Value::Null) => return Ordering::Equal,
+        (Value::Null, _) => return Ordering::Greater,
+        (_, Value::Null) => return Ordering::Less,
+        (Value::Number(x), Value::Number(y)) => {
+            let (x, y) = (x.as_f64().unwrap_or(0.0), y.as_f64().unwrap_or(0.0));
+            x.partial_cmp(&y).unwrap_or(Ordering::Equal)
+        }
+        _ => cell(a).cmp(&cell(b)),
+    };
+    if descending {
+        order.reverse()
+    } else {
+        order
+    }
+}
+
+/// How many levels of nested objects `flatten` turns into columns unless told otherwise.
+pub const DEFAULT_MAX_DEPTH: usize = 2;
+
+/// Which items the row formats show, in what order, and which of their fields.
+#[derive(Debug, Clone)]
+pub struct View {
+    /// Dotted paths to show; empty means the defaults, or every field.
+    pub columns: Vec<String>,
+    pub sort_by: Option<SortKey>,
+    /// All of these have to match for an item to be shown.
+    pub filters: Vec<Filter>,
+    /// Levels of nested objects to spread over dotted columns; see `flatten`.
+    pub max_depth: usize,
+}
+
+impl Default for View {
+    fn default() -> View {
+        View {
+            columns: Vec::new(),
+            sort_by: None,
+            filters: Vec::new(),
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+impl View {
+    /// Filters, sorts and picks the columns of `objects`.  `defaults` are the columns used
+    /// when none were asked for; when they are empty too, every field is shown.
+    pub fn rows(&self, objects: Vec<Value>, defaults: &[&str]) -> Rows {
+        let mut objects: Vec<Value> = objects
+            .into_iter()
+            .filter(|o| self.filters.iter().all(|f| f.matches(o)))
+            .collect();
+        if let Some(key) = &self.sort_by {
+            objects.sort_by(|a, b| {
+                compare(lookup(a, &key.path), lookup(b, &key.path), key.descending)
+            });
+        }
+        let columns: Vec<String> = if !self.columns.is_empty() {
+            self.columns.clone()
+        } else if !defaults.is_empty() {
+            defaults.iter().map(|c| c.to_string()).collect()
+        } else {
+            return Rows::from_objects(&objects);
+        };
+        let rows = objects
+            .iter()
+            .map(|o| columns.iter().map(|c| lookup(o, c).clone()).collect())
+            .collect();
+        Rows { columns, rows }
+    }
+}
+
+/// An array as one short cell: its elements joined by commas when they are all scalars,
+/// otherwise just how many there are.
+fn summarize(items: &[Value]) -> Value {
+    if items.iter().any(|v| v.is_object() || v.is_array()) {
+        let plural = if items.len() == 1 { "" } else { "s" };
+        Value::String(format!("{} item{plural}", items.len()))
+    } else {
+        Value::String(items.iter().map(cell).collect::<Vec<_>>().join(", "))
+    }
+}
+
+fn flatten_into(path: String, value: Value, depth: usize, out: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Object(fields) if depth > 0 && !fields.is_empty() => {
+            for (key, value) in fields {
+                flatten_into(format!("{path}.{key}"), value, depth - 1, out);
+            }
+        }
+        Value::Array(items) => out.push((path, summarize(&items))),
+        value => out.push((path, value)),
+    }
+}
+
+/// Spreads nested objects over dotted columns, such as `settings.pg_settings.max_connections`,
+/// down to `max_depth` levels, and summarizes arrays, so that cells hold short values rather
+/// than JSON documents.  Objects deeper than that stay JSON; a `max_depth` of 0 leaves every
+/// cell as it is.
+pub fn flatten(rows: Rows, max_depth: usize) -> Rows {
+    if max_depth == 0 {
+        return rows;
+    }
+    let Rows { columns, rows } = rows;
+    // For each row, the flattened cells of each of its columns.
+    let flat: Vec<Vec<Vec<(String, Value)>>> = rows
+        .into_iter()
+        .map(|row| {
+            columns
+                .iter()
+                .zip(row)
+                .map(|(name, value)| {
+                    let mut cells = Vec::new();
+                    flatten_into(name.clone(), value, max_depth, &mut cells);
+                    cells
+                })
+                .collect()
+        })
+        .collect();
+    // A column's dotted columns stay together, in the order they are first seen.  Rows
+    // where the column is null don't add the column itself next to them.
+    let mut names: Vec<String> = Vec::new();
+    for (i, column) in columns.iter().enumerate() {
+        let mut seen: Vec<String> = Vec::new();
+        for (name, value) in flat.iter().flat_map(|row| &row[i]) {
+            if !value.is_null() && !seen.contains(name) {
+                seen.push(name.clone());
+            }
+        }
+        if seen.is_empty() {
+            seen.push(column.clone());
+        }
+        names.extend(seen);
+    }
+    let rows = flat
+        .into_iter()
+        .map(|row| {
+            let cells: Vec<(String, Value)> = row.into_iter().flatten().collect();
+            names
+                .iter()
+                .map(|name| {
+                    cells
+                        .iter()
+                        .find(|(n, _)| n == name)
+                        .map_or(Value::Null, |(_, v)| v.clone())
+                })
+                .collect()
+        })
+        .collect();
+    Rows {
+        columns: names,
+        rows,
+    }
+}
+
+/// A value as it appears in a table cell or CSV field: strings without JSON quotes and
+/// NULL as nothing.
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// One row as a JSON object with the columns in order, which serde_json's own map sorts.
+struct Record<'a>(&'a [String], &'a [Value]);
+
+impl Serialize for Record<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, value) in self.0.iter().zip(self.1) {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+fn new_table() -> Table {
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic);
+    table
+}
+
+fn delimited(rows: &Rows, delimiter: u8) -> String {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(Vec::new());
+    // Writing to memory can't fail.
+    writer.write_record(&rows.columns).unwrap();
+    for row in &rows.rows {
+        writer.write_record(row.iter().map(cell)).unwrap();
+    }
+    String::from_utf8(writer.into_inner().unwrap()).unwrap()
+}
+
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', "<br>")
+}
+
+fn markdown(rows: &Rows) -> String {
+    let line = |cells: Vec<String>| format!("| {} |\n", cells.join(" | "));
+    let mut out = line(rows.columns.iter().map(|c| markdown_cell(c)).collect());
+    out.push_str(&line(
+        rows.columns.iter().map(|_| "---".to_string()).collect(),
+    ));
+    for row in &rows.rows {
+        out.push_str(&line(row.iter().map(|v| markdown_cell(&cell(v))).collect()));
+    }
+    out
+}
+
+/// Each row as its own two column table of names and values, like psql's \x.
+fn vertical(rows: &Rows) -> String {
+    let mut out = String::new();
+    for (n, row) in rows.rows.iter().enumerate() {
+        let mut table = new_table();
+        for (name, value) in rows.columns.iter().zip(row) {
+            table.add_row(vec![name.clone(), cell(value)]);
+        }
+        out.push_str(&format!("-[ RECORD {} ]-\n{table}\n", n + 1));
+    }
+    out
+}
+
+/// Renders `rows` in `format`, one of FORMATS.
+pub fn render(rows: &Rows, format: &str) -> String {
+    let records = rows.rows.iter().map(|r| Record(&rows.columns, r));
+    match format {
+        "table" => {
+            let mut table = new_table();
+            table.set_header(&rows.columns);
+            for row in &rows.rows {
+                table.add_row(row.iter().map(cell));
+            }
+            format!("{table}\n")
+        }
+        "csv" => delimited(rows, b','),
+        "tsv" => delimited(rows, b'\t'),
+        // Serializing JSON values can't fail.
+        "json" => serde_json::to_string_pretty(&records.collect::<Vec<_>>()).unwrap() + "\n",
+        "ndjson" => records
+            .map(|r| serde_json::to_string(&r).unwrap() + "\n")
+            .collect(),
+        "yaml" => serde_yaml::to_string(&records.collect::<Vec<_>>()).unwrap(),
+        "markdown" => markdown(rows),
+        "vertical" => vertical(rows),
+        _ => panic!("Unknown format: {format}"),
+    }
+}
+
+/// A whole JSON document as YAML.
+pub fn to_yaml(value: &Value) -> String {
+    // Any JSON value has a YAML form.
+    serde_yaml::to_string(value).unwrap()
+}
+
+/// Values for scripts: a string as itself, an array one element per line, and anything else
+/// as compact JSON.
+pub fn raw(value: &Value) -> String {
+    match value {
+        Value::Array(items) => items.iter().map(|v| cell(v) + "\n").collect(),
+        Value::Null => "\n".to_string(),
+        v => cell(v) + "\n",
+    }
+}
+
+/// Prints `rows` in `format`, one of FORMATS.
+pub fn print(rows: &Rows, format: &str) {
+    print!("{}", render(rows, format));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rows() -> Rows {
+        Rows {
+            columns: vec!["name".to_string(), "size".to_string(), "tags".to_string()],
+            rows: vec![
+                vec![json!("a|b"), json!(3), json!(["x"])],
+                vec![json!("c, d"), Value::Null, json!(null)],
+            ],
+        }
+    }
+
+    #[test]
+    fn delimited_formats_leave_strings_unquoted() {
+        assert_eq!(
+            render(&rows(), "csv"),
+            "name,size,tags\na|b,3,\"[\"\"x\"\"]\"\n\"c, d\",,\n"
+        );
+        assert_eq!(
+            render(&rows(), "tsv"),
+            "name\tsize\ttags\na|b\t3\t\"[\"\"x\"\"]\"\nc, d\t\t\n"
+        );
+    }
+
+    #[test]
+    fn json_formats_keep_column_order() {
+        let rows = Rows {
+            columns: vec!["z".to_string(), "a".to_string()],
+            rows: vec![vec![json!(1), json!("one")]],
+        };
+        assert_eq!(render(&rows, "ndjson"), "{\"z\":1,\"a\":\"one\"}\n");
+        let parsed: Value = serde_json::from_str(&render(&rows, "json")).unwrap();
+        assert_eq!(parsed, json!([{"z": 1, "a": "one"}]));
+    }
+
+    #[test]
+    fn yaml_lists_rows_as_mappings() {
+        let rows = Rows {
+            columns: vec!["z".to_string(), "a".to_string()],
+            rows: vec![vec![json!(1), Value::Null]],
+        };
+        assert_eq!(render(&rows, "yaml"), "- z: 1\n  a: null\n");
+        assert_eq!(to_yaml(&json!({"k": ["v"]})), "k:\n- v\n");
+    }
+
+    #[test]
+    fn raw_prints_strings_unquoted() {
+        assert_eq!(raw(&json!("br-1")), "br-1\n");
+        assert_eq!(raw(&json!(["a", 2, {"k": null}])), "a\n2\n{\"k\":null}\n");
+        assert_eq!(raw(&json!({"k": "v"})), "{\"k\":\"v\"}\n");
+    }
+
+    #[test]
+    fn markdown_escapes_pipes() {
+        assert_eq!(
+            render(&rows(), "markdown"),
+            "| name | size | tags |\n| --- | --- | --- |\n| a\\|b | 3 | [\"x\"] |\n| c, d |  |  |\n"
+        );
+    }
+
+    #[test]
+    fn vertical_prints_a_record_per_row() {
+        let out = render(&rows(), "vertical");
+        assert!(out.starts_with("-[ RECORD 1 ]-\n"));
+        assert!(out.contains("-[ RECORD 2 ]-\n"));
+    }
+
+    #[test]
+    fn lookup_follows_dotted_paths() {
+        let v = json!({"settings": {"quota": {"active_time_seconds": 60}}, "hosts": ["a", "b"]});
+        assert_eq!(lookup(&v, "settings.quota.active_time_seconds"), &json!(60));
+        assert_eq!(lookup(&v, "hosts.1"), &json!("b"));
+        assert_eq!(lookup(&v, "settings.nope.deeper"), &Value::Null);
+    }
+
+    #[test]
+    fn sort_and_filter_options_parse() {
+        let key = SortKey::parse("created_at:desc").unwrap();
+        assert_eq!((key.path.as_str(), key.descending), ("created_at", true));
+        assert!(!SortKey::parse("a.b").unwrap().descending);
+        assert!(SortKey::parse("name:sideways").is_err());
+
+        let filter = Filter::parse("settings.pooler_mode!=session").unwrap();
+        assert_eq!(filter.path, "settings.pooler_mode");
+        assert_eq!(filter.value, "session");
+        assert!(filter.negate);
+        assert!(Filter::parse("name").is_err());
+    }
+
+    #[test]
+    fn view_filters_sorts_and_picks_columns() {
+        let objects = vec![
+            json!({"id": "b", "cu": 2, "state": "idle", "s": {"region": "eu"}}),
+            json!({"id": "a", "cu": 10, "state": "active", "s": {"region": "us"}}),
+            json!({"id": "c", "cu": null, "state": "active", "s": {"region": "us"}}),
+        ];
+        let view = View {
+            columns: vec!["id".to_string(), "s.region".to_string()],
+            sort_by: Some(SortKey::parse("cu:desc").unwrap()),
+            filters: vec![Filter::parse("state=active").unwrap()],
+            ..View::default()
+        };
+        let rows = view.rows(objects.clone(), &["id"]);
+        assert_eq!(rows.columns, vec!["id", "s.region"]);
+        assert_eq!(
+            rows.rows,
+            vec![vec![json!("a"), json!("us")], vec![json!("c"), json!("us")]]
+        );
+
+        // Ascending, numbers compare as numbers and nulls go last.
+        let view = View {
+            sort_by: Some(SortKey::parse("cu").unwrap()),
+            ..View::default()
+        };
+        let rows = view.rows(objects.clone(), &["id", "cu"]);
+        let ids: Vec<&Value> = rows.rows.iter().map(|r| &r[0]).collect();
+        assert_eq!(ids, vec!["b", "a", "c"]);
+
+        assert_eq!(
+            View::default().rows(objects, &[]).columns,
+            vec!["cu", "id", "s", "state"]
+        );
+    }
+
+    #[test]
+    fn nested_objects_become_dotted_columns() {
+        let rows = Rows::from_objects(&[
+            json!({"id": "a", "settings": {"pg": {"max_connections": 100}, "mode": "tx"}}),
+            json!({"id": "b", "settings": null, "hosts": ["h1", "h2"], "roles": [{"n": 1}]}),
+        ]);
+        let flat = flatten(rows, 2);
+        let columns = [
+            "id",
+            "settings.mode",
+            "settings.pg.max_connections",
+            "hosts",
+            "roles",
+        ];
+        assert_eq!(flat.columns, columns);
+        let a = [
+            json!("a"),
+            json!("tx"),
+            json!(100),
+            Value::Null,
+            Value::Null,
+        ];
+        assert_eq!(flat.rows[0], a);
+        let b = [
+            json!("b"),
+            Value::Null,
+            Value::Null,
+            json!("h1, h2"),
+            json!("1 item"),
+        ];
+        assert_eq!(flat.rows[1], b);
+
+        // Deeper objects stay JSON, and 0 leaves everything alone.
+        let rows = || Rows::from_objects(&[json!({"s": {"pg": {"max": 1}}, "a": [1]})]);
+        let shallow = flatten(rows(), 1);
+        assert_eq!(shallow.columns, vec!["a", "s.pg"]);
+        assert_eq!(shallow.rows[0], vec![json!("1"), json!({"max": 1})]);
+        assert_eq!(flatten(rows(), 0), rows());
+    }
+
+    #[test]
+    fn objects_become_rows_under_every_key() {
+        let rows =
+            Rows::from_objects(&[json!({"id": 1, "name": "a"}), json!({"id": 2, "x": true})]);
+        assert_eq!(rows.columns, vec!["id", "name", "x"]);
+        assert_eq!(rows.rows[1], vec![json!(2), Value::Null, json!(true)]);
+    }
+}
@@ -0,0 +1,352 @@
+//! `neon-cli shell`: a small psql-like REPL that keeps one connection open.
+
+use crate::Query;
+use postgres::Client;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+const HELP: &str = r#"Statements run once they end with a semicolon and may span several lines.
+  \dt          list tables
+  \d TABLE     describe a table's columns
+  \timing      toggle printing how long each statement took
+  \x           toggle expanded (one record per table) output
+  \?           show this help
+  \q           quit"#;
+
+const LIST_TABLES: &str = r#"select n.nspname::text as "Schema", c.relname::text as "Name",
+    case c.relkind when 'p' then 'partitioned table' else 'table' end as "Type",
+    pg_get_userbyid(c.relowner)::text as "Owner"
+from pg_class c join pg_namespace n on n.oid = c.relnamespace
+where c.relkind in ('r', 'p')
+    and n.nspname not in ('pg_catalog', 'information_schema')
+    and n.nspname !~ '^pg_toast'
+order by 1, 2"#;
+
+/// Columns of `table`, which may be schema qualified; the `regclass` cast resolves it
+/// through the search path and reports a missing table the way Postgres words it.
+fn describe_sql(table: &str) -> String {
+    let literal = table.replace('\'', "''");
+    format!(
+        r#"select a.attname::text as "Column", format_type(a.atttypid, a.atttypmod) as "Type",
+    case when a.attnotnull then 'not null' else '' end as "Nullable",
+    coalesce(pg_get_expr(d.adbin, d.adrelid), '') as "Default"
+from pg_attribute a
+    left join pg_attrdef d on d.adrelid = a.attrelid and d.adnum = a.attnum
+where a.attrelid = '{literal}'::regclass and a.attnum > 0 and not a.attisdropped
+order by a.attnum"#
+    )
+}
+
+/// Where the shell keeps its history between runs.
+fn history_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("neon-cli").join("history"))
+}
+
+#[derive(Debug, PartialEq)]
+enum Meta {
+    ListTables,
+    Describe(String),
+    Timing,
+    Expanded,
+    Help,
+    Quit,
+}
+
+impl Meta {
+    fn parse(line: &str) -> Result<Meta, String> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        match (command, words.next()) {
+            ("\\dt", _) | ("\\d", None) => Ok(Meta::ListTables),
+            ("\\d", Some(table)) => Ok(Meta::Describe(table.to_string())),
+            ("\\timing", _) => Ok(Meta::Timing),
+            ("\\x", _) => Ok(Meta::Expanded),
+            ("\\?", _) => Ok(Meta::Help),
+            ("\\q", _) => Ok(Meta::Quit),
+            _ => Err(format!("Invalid command {command}. Try \\? for help.")),
+        }
+    }
+}
+
+enum Scan {
+    Normal,
+    Quoted(u8),
+    Dollar(String),
+    LineComment,
+    BlockComment,
+}
+
+/// The `$tag$` opening a dollar-quoted string at the start of `s`, if there is one.
+fn dollar_tag(s: &str) -> Option<&str> {
+    let end = s[1..].find('$')? + 1;
+    let tag = &s[1..end];
+    let valid = tag.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && !tag.starts_with(|c: char| c.is_ascii_digit());
+    valid.then(|| &s[..=end])
+}
+
+/// Splits the complete statements (those ended by `;`) off the front of `buffer` and
+/// returns them with whatever is left over.  Semicolons inside quotes, dollar quotes and
+/// comments don't end a statement.
+fn split_statements(buffer: &str) -> (Vec<String>, String) {
+    let bytes = buffer.as_bytes();
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut state = Scan::Normal;
+    let mut i = 0;
+    while i < bytes.len() {
+        // Compare bytes rather than slicing `buffer`: `i` may fall inside a multibyte
+        // character, and every byte this looks for is ASCII.
+        let rest = &bytes[i..];
+        match &state {
+            Scan::Normal if rest.starts_with(b"--") => state = Scan::LineComment,
+            Scan::Normal if rest.starts_with(b"/*") => {
+                state = Scan::BlockComment;
+                i += 1;
+            }
+            Scan::Normal => match bytes[i] {
+                q @ (b'\'' | b'"') => state = Scan::Quoted(q),
+                b'$' => {
+                    if let Some(tag) = dollar_tag(&buffer[i..]) {
+                        i += tag.len() - 1;
+                        state = Scan::Dollar(tag.to_string());
+                    }
+                }
+                b';' => {
+                    let statement = buffer[start..i].trim();
+                    if !statement.is_empty() {
+                        statements.push(statement.to_string());
+                    }
+                    start = i + 1;
+                }
+                _ => {}
+            },
+            Scan::Quoted(q) if bytes[i] == *q => state = Scan::Normal,
+            Scan::Dollar(tag) if rest.starts_with(tag.as_bytes()) => {
+                i += tag.len() - 1;
+                state = Scan::Normal;
+            }
+            Scan::LineComment if bytes[i] == b'\n' => state = Scan::Normal,
+            Scan::BlockComment if rest.starts_with(b"*/") => {
+                i += 1;
+                state = Scan::Normal;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    (statements, buffer[start..].to_string())
+}
+
+fn on_off(flag: bool) -> &'static str {
+    if flag {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+struct Shell {
+    client: Client,
+    timing: bool,
+    expanded: bool,
+}
+
+impl Shell {
+    /// Runs one statement and prints its result.  A failed statement is reported and the
+    /// session carries on, like psql.
+    fn execute(&mut self, sql: &str) {
+        let format = if self.expanded { "vertical" } else { "table" };
+        let query = Query {
+            query: sql.to_string(),
+            format: format.to_string(),
+        };
+        let started = Instant::now();
+        if let Err(e) = query.query(&mut self.client) {
+            eprintln!("{e}");
+        }
+        if self.timing {
+            let elapsed = started.elapsed().as_secs_f64() * 1000.0;
+            println!("Time: {elapsed:.3} ms");
+        }
+    }
+
+    /// Runs a meta-command.  Returns false when the shell should exit.
+    fn meta(&mut self, meta: Meta) -> bool {
+        match meta {
+            Meta::ListTables => self.execute(LIST_TABLES),
+            Meta::Describe(table) => self.execute(&describe_sql(&table)),
+            Meta::Timing => {
+                self.timing = !self.timing;
+                println!("Timing is {}.", on_off(self.timing));
+            }
+            Meta::Expanded => {
+                self.expanded = !self.expanded;
+                println!("Expanded display is {}.", on_off(self.expanded));
+            }
+            Meta::Help => println!("{HELP}"),
+            Meta::Quit => return false,
+        }
+        true
+    }
+}
+
+/// Reads statements and meta-commands until `\q` or end of input.
+pub fn run(client: Client, database: &str) -> Result<(), Box<dyn Error>> {
+    let mut editor = DefaultEditor::new()?;
+    let history = history_path();
+    if let Some(path) = &history {
+        // There is no history yet on the first run.
+        let _ = editor.load_history(path);
+    }
+    let mut shell = Shell {
+        client,
+        timing: false,
+        expanded: false,
+    };
+    println!("Type \\? for help, \\q to quit.");
+    let result = read_eval(&mut editor, &mut shell, database);
+
+    // Saved however the session ended, so a lost connection doesn't lose the history too.
+    // An error from the session itself is the one worth reporting.
+    match &history {
+        Some(path) => result.and(save_history(&mut editor, path)),
+        None => result,
+    }
+}
+
+fn save_history(editor: &mut DefaultEditor, path: &Path) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    editor.save_history(path)?;
+    Ok(())
+}
+
+/// The shell's loop: runs what is typed until `\q`, end of input or an error.
+fn read_eval(
+    editor: &mut DefaultEditor,
+    shell: &mut Shell,
+    database: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut buffer = String::new();
+    loop {
+        let marker = if buffer.is_empty() { '=' } else { '-' };
+        let line = match editor.readline(&format!("{database}{marker}> ")) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                buffer.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if line.trim().is_empty() && buffer.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str())?;
+
+        if buffer.is_empty() && line.trim_start().starts_with('\\') {
+            match Meta::parse(line.trim()) {
+                Ok(meta) => {
+                    if !shell.meta(meta) {
+                        break;
+                    }
+                }
+                Err(e) => eprintln!("{e}"),
+            }
+            continue;
+        }
+        buffer.push_str(&line);
+        buffer.push('\n');
+        let (statements, rest) = split_statements(&buffer);
+        buffer = if rest.trim().is_empty() {
+            String::new()
+        } else {
+            rest
+        };
+        for statement in statements {
+            shell.execute(&statement);
+        }
+        if shell.client.is_closed() {
+            return Err("the connection to the server was lost".into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statements_end_at_semicolons() {
+        let (done, rest) = split_statements("select 1;\nselect\n  2; select");
+        assert_eq!(done, vec!["select 1", "select\n  2"]);
+        assert_eq!(rest, " select");
+
+        let (done, rest) = split_statements("select 1\n");
+        assert!(done.is_empty());
+        assert_eq!(rest, "select 1\n");
+    }
+
+    #[test]
+    fn quoted_semicolons_do_not_end_statements() {
+        let sql = "select 'a;b', \"c;d\" -- e;f\n, 1 /* g; */;";
+        let (done, rest) = split_statements(sql);
+        assert_eq!(done, vec![&sql[..sql.len() - 1]]);
+        assert!(rest.is_empty());
+
+        let body = "create function f() returns int as $fn$ select 1; $fn$ language sql";
+        let (done, _) = split_statements(&format!("{body};"));
+        assert_eq!(done, vec![body]);
+
+        let (done, rest) = split_statements("select 'it''s; fine';");
+        assert_eq!(done, vec!["select 'it''s; fine'"]);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn unterminated_quotes_keep_the_statement_open() {
+        let (done, rest) = split_statements("select 'a;\n");
+        assert!(done.is_empty());
+        assert_eq!(rest, "select 'a;\n");
+
+        // A positional parameter is not a dollar quote.
+        let (done, _) = split_statements("select $1;");
+        assert_eq!(done, vec!["select $1"]);
+    }
+
+    #[test]
+    fn non_ascii_text_is_split_on_character_boundaries() {
+        let (done, rest) = split_statements("select 'héllo; wörld', ünï;\nselect '日本';  -- é\n");
+        assert_eq!(done, vec!["select 'héllo; wörld', ünï", "select '日本'"]);
+        assert_eq!(rest, "  -- é\n");
+
+        let (done, rest) = split_statements("select $é$ ü; $é$; /* ö; */ select ä");
+        assert_eq!(done, vec!["select $é$ ü; $é$"]);
+        assert_eq!(rest, " /* ö; */ select ä");
+    }
+
+    #[test]
+    fn meta_commands_parse() {
+        assert_eq!(Meta::parse("\\dt"), Ok(Meta::ListTables));
+        assert_eq!(Meta::parse("\\d"), Ok(Meta::ListTables));
+        assert_eq!(
+            Meta::parse("\\d  public.users"),
+            Ok(Meta::Describe("public.users".to_string()))
+        );
+        assert_eq!(Meta::parse("\\timing"), Ok(Meta::Timing));
+        assert_eq!(Meta::parse("\\x"), Ok(Meta::Expanded));
+        assert_eq!(Meta::parse("\\q"), Ok(Meta::Quit));
+        assert!(Meta::parse("\\copy").is_err());
+    }
+
+    #[test]
+    fn describe_escapes_the_table_name() {
+        assert!(describe_sql("o'brien").contains("'o''brien'::regclass"));
+    }
+}
@@ -0,0 +1,1400 @@
+//! End-to-end runs of every API command against the fake Neon server.
+
+mod common;
+
+use common::*;
+use serde_json::{json, Value};
+
+fn assert_request(fake: &FakeNeon, method: &str, path: &str) {
+    let req = fake.last_request();
+    assert_eq!(req.method, method);
+    assert_eq!(req.path, path);
+    assert_eq!(
+        req.authorization.as_deref(),
+        Some(format!("Bearer {API_KEY}").as_str())
+    );
+}
+
+#[test]
+fn keys_list_create_and_revoke() {
+    let fake = FakeNeon::start();
+
+    let out = fake.run(&["keys", "-a", "list"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "GET", "/api_keys");
+    assert_eq!(json_stdout(&out)[0]["name"], "ci");
+
+    let out = fake.run(&["keys", "-a", "create", "-n", "deploy"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "POST", "/api_keys");
+    assert_eq!(
+        fake.last_request().body,
+        Some(json!({"key_name": "deploy"}))
+    );
+    assert_eq!(json_stdout(&out)["key"], "secret-key-value");
+
+    let out = fake.run(&["keys", "-a", "revoke", "-n", "1"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "DELETE", "/api_keys/1");
+    assert_eq!(json_stdout(&out)["revoked"], true);
+}
+
+#[test]
+fn keys_list_as_table() {
+    let fake = FakeNeon::start();
+    let out = fake.run(&["keys", "-a", "list", "-f", "table"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    let table = stdout(&out);
+    assert!(table.contains("last_used_at"), "{table}");
+    assert!(table.contains("ci"), "{table}");
+    // Tables show a few columns per resource unless others are asked for.
+    assert!(!table.contains("last_used_from_addr"), "{table}");
+
+    let args = [
+        "keys",
+        "-a",
+        "list",
+        "-f",
+        "table",
+        "--columns",
+        "name,last_used_from_addr",
+    ];
+    let table = stdout(&fake.run(&args));
+    assert!(table.contains("last_used_from_addr"), "{table}");
+    assert!(!table.contains("created_at"), "{table}");
+}
+
+#[test]
+fn keys_list_as_csv_and_markdown() {
+    let fake = FakeNeon::start();
+    let out = fake.run(&["keys", "-a", "list", "-f", "csv"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    let csv = stdout(&out);
+    let mut lines = csv.lines();
+    let header: Vec<&str> = lines.next().unwrap().split(',').collect();
+    let first: Vec<&str> = lines.next().unwrap().split(',').collect();
+    let name = header.iter().position(|h| *h == "name").unwrap();
+    assert_eq!(first[name], "ci", "{csv}");
+
+    let out = fake.run(&["keys", "-a", "list", "-f", "markdown"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert!(stdout(&out).contains("| ci |"), "{}", stdout(&out));
+}
+
+#[test]
+fn projects_list_details_and_delete() {
+    let fake = FakeNeon::start();
+
+    let out = fake.run(&["projects", "-a", "list-projects"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "GET", "/projects");
+    assert_eq!(json_stdout(&out)["projects"][0]["id"], PROJECT);
+
+    let out = fake.run(&["projects", "-a", "project-details", "-p", PROJECT]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "GET", &format!("/projects/{PROJECT}"));
+    let project = json_stdout(&out);
+    assert_eq!(project["project"]["name"], "test-project");
+    // Fields the models don't name are still printed.
+    assert_eq!(
+        project["project"]["settings"]["quota"]["active_time_seconds"],
+        100
+    );
+
+    let out = fake.run(&["projects", "-a", "delete-project", "-p", PROJECT]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "DELETE", &format!("/projects/{PROJECT}"));
+    assert!(fake.state.lock().unwrap().projects.is_empty());
+}
+
+#[test]
+fn projects_create_sends_the_chosen_settings() {
+    let fake = FakeNeon::start();
+    let out = fake.run(&[
+        "projects",
+        "-a",
+        "create-project",
+        "--name",
+        "analytics",
+        "--region",
+        "aws-eu-central-1",
+        "--pg-version",
+        "16",
+        "--default-branch",
+        "production",
+        "--min-cu",
+        "0.5",
+        "--max-cu",
+        "4",
+        "--history-retention",
+        "86400",
+    ]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    let req = fake.last_request();
+    assert_eq!(
+        (req.method.as_str(), req.path.as_str()),
+        ("POST", "/projects")
+    );
+    assert_eq!(
+        req.body.unwrap(),
+        json!({"project": {
+            "name": "analytics", "region_id": "aws-eu-central-1", "pg_version": 16,
+            "branch": {"name": "production"}, "history_retention_seconds": 86400,
+            "default_endpoint_settings": {
+                "autoscaling_limit_min_cu": 0.5, "autoscaling_limit_max_cu": 4.0,
+            },
+        }})
+    );
+    let created = json_stdout(&out);
+    assert_eq!(created["project"]["name"], "analytics");
+    assert_eq!(created["branch"]["name"], "production");
+    let uri = created["connection_uris"][0]["connection_uri"]
+        .as_str()
+        .unwrap();
+    assert!(uri.starts_with("postgres://owner:pw@"), "{uri}");
+
+    let out = fake.run(&["projects", "-a", "create-project", "-f", "table"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert!(stdout(&out).contains("Connection URI: postgres://owner:pw@"));
+    assert!(stdout(&out).contains("region_id"));
+
+    // The table goes through --columns and --query like any other.
+    let table = ["projects", "-a", "create-project", "-f", "table"];
+    let out = fake.run(&[&table[..], &["--columns", "id,name"]].concat());
+    assert!(!stdout(&out).contains("region_id"), "{}", stdout(&out));
+    assert!(stdout(&out).contains("Connection URI: postgres://owner:pw@"));
+    let out = fake.run(&[&table[..], &["--query", "project.name"]].concat());
+    assert!(!stdout(&out).contains("Connection URI"), "{}", stdout(&out));
+}
+
+#[test]
+fn projects_update_changes_settings_and_prints_connection_uris() {
+    let fake = FakeNeon::start();
+    let out = fake.run(&[
+        "projects",
+        "-a",
+        "update-project",
+        "-p",
+        PROJECT,
+        "--name",
+        "renamed",
+        "--allowed-ips",
+        "192.0.2.1,198.51.100.0/24",
+        "--quota",
+        "active_time_seconds=3600",
+        "--quota",
+        "written_data_bytes=1000000",
+        "--logical-replication",
+    ]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    let patch = fake
+        .requests()
+        .into_iter()
+        .find(|r| r.method == "PATCH")
+        .unwrap();
+    assert_eq!(patch.path, format!("/projects/{PROJECT}"));
+    assert_eq!(
+        patch.body.unwrap(),
+        json!({"project": {"name": "renamed", "settings": {
+            "allowed_ips": {"ips": ["192.0.2.1", "198.51.100.0/24"]},
+            "quota": {"active_time_seconds": 3600, "written_data_bytes": 1000000},
+            "enable_logical_replication": true,
+        }}})
+    );
+    let updated = json_stdout(&out);
+    assert_eq!(updated["project"]["name"], "renamed");
+    assert_eq!(
+        updated["connection_uris"],
+        json!([{"connection_uri": "postgres://alice:pw@ep-main.us-east-2.aws.neon.tech/neondb"}])
+    );
+}
+
+#[test]
+fn projects_update_looks_up_uris_on_the_default_branch() {
+    let fake = FakeNeon::start();
+    {
+        let mut state = fake.state.lock().unwrap();
+        state.branches[0]["default"] = json!(false);
+        state
+            .branches
+            .push(json!({"id": "br-default", "name": "dev", "default": true}));
+    }
+    let update = [
+        "projects",
+        "-a",
+        "update-project",
+        "-p",
+        PROJECT,
+        "--name",
+        "renamed",
+    ];
+    let out = fake.run(&update);
+    assert!(out.status.success(), "{}", stderr(&out));
+    let databases = format!("/projects/{PROJECT}/branches/br-default/databases");
+    assert!(fake.requests().iter().any(|r| r.path == databases));
+
+    // Without a `default` flag on any branch, the deprecated `primary` one is used.
+    {
+        let mut state = fake.state.lock().unwrap();
+        state.branches.truncate(1);
+        state.branches[0].as_object_mut().unwrap().remove("default");
+    }
+    let out = fake.run(&update);
+    assert!(out.status.success(), "{}", stderr(&out));
+    let databases = format!("/projects/{PROJECT}/branches/{BRANCH}/databases");
+    assert!(fake.requests().iter().any(|r| r.path == databases));
+}
+
+#[test]
+fn projects_update_rejects_create_only_and_bad_settings() {
+    let fake = FakeNeon::start();
+    let update = ["projects", "-a", "update-project", "-p", PROJECT];
+    for (options, message) in [
+        (&[][..], "needs at least one setting to change"),
+        (
+            &["--region", "aws-us-east-2"][..],
+            "--region can only be chosen by create-project",
+        ),
+        (
+            &["--min-cu", "2", "--max-cu", "1"][..],
+            "can't be more than --max-cu",
+        ),
+        (&["--min-cu", "0.3"][..], "whole number of compute units"),
+        (
+            &["--quota", "cpu=1"][..],
+            "expected one of active_time_seconds",
+        ),
+    ] {
+        let out = fake.run(&[&update[..], options].concat());
+        assert!(!out.status.success());
+        assert!(stderr(&out).contains(message), "{}", stderr(&out));
+    }
+    assert!(fake.requests().is_empty());
+}
+
+#[test]
+fn projects_list_as_table() {
+    let fake = FakeNeon::start();
+    let out = fake.run(&["projects", "-a", "list-projects", "-f", "table"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert!(stdout(&out).contains("test-project"));
+}
+
+#[test]
+fn branch_read_actions() {
+    let fake = FakeNeon::start();
+    let base = format!("/projects/{PROJECT}/branches");
+    let cases = [
+        ("list-branches", base.clone(), "branches"),
+        ("branch-details", format!("{base}/{BRANCH}"), "branch"),
+        (
+            "list-endpoints",
+            format!("{base}/{BRANCH}/endpoints"),
+            "endpoints",
+        ),
+        ("list-roles", format!("{base}/{BRANCH}/roles"), "roles"),
+        (
+            "list-databases",
+            format!("{base}/{BRANCH}/databases"),
+            "databases",
+        ),
+    ];
+    for (action, path, key) in cases {
+        let out = fake.run(&["branch", "-a", action, "-p", PROJECT, "-b", BRANCH]);
+        assert!(out.status.success(), "{action}: {}", stderr(&out));
+        assert_request(&fake, "GET", &path);
+        assert!(
+            !json_stdout(&out)[key].is_null(),
+            "{action}: {}",
+            stdout(&out)
+        );
+    }
+}
+
+#[test]
+fn responses_print_only_what_the_api_sent() {
+    let fake = FakeNeon::start();
+    let args = [
+        "branch",
+        "-a",
+        "role-details",
+        "-p",
+        PROJECT,
+        "-b",
+        BRANCH,
+        "-r",
+        "alice",
+    ];
+
+    let sparse = json!({"role": {"name": "alice", "branch_id": BRANCH}});
+    fake.respond_next(200, sparse.clone());
+    let out = fake.run(&args);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(json_stdout(&out), sparse);
+
+    // Nulls decode like missing fields; a missing name can't be worked with.
+    fake.respond_next(200, json!({"role": {"name": "alice", "protected": null}}));
+    let out = fake.run(&args);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(json_stdout(&out), json!({"role": {"name": "alice"}}));
+
+    fake.respond_next(200, json!({"role": {"branch_id": BRANCH}}));
+    let out = fake.run(&args);
+    assert!(!out.status.success());
+    assert!(
+        stderr(&out).contains("Unexpected response from the Neon API"),
+        "{}",
+        stderr(&out)
+    );
+}
+
+#[test]
+fn branch_role_details_and_delete() {
+    let fake = FakeNeon::start();
+    let path = format!("/projects/{PROJECT}/branches/{BRANCH}/roles/alice");
+
+    let args = ["branch", "-p", PROJECT, "-b", BRANCH, "-r", "alice", "-a"];
+    let out = fake.run(&[&args[..], &["role-details"]].concat());
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "GET", &path);
+    assert_eq!(json_stdout(&out)["role"]["name"], "alice");
+
+    let out = fake.run(&[&args[..], &["role-delete"]].concat());
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "DELETE", &path);
+    assert_eq!(json_stdout(&out)["operations"][0]["action"], "apply_config");
+}
+
+#[test]
+fn branch_role_create_reset_and_reveal_password() {
+    let fake = FakeNeon::start();
+    let roles = format!("/projects/{PROJECT}/branches/{BRANCH}/roles");
+    let args = ["branch", "-p", PROJECT, "-b", BRANCH, "-r", "svc", "-a"];
+
+    let out = fake.run(&[&args[..], &["role-create"]].concat());
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "POST", &roles);
+    assert_eq!(
+        fake.last_request().body.unwrap(),
+        json!({"role": {"name": "svc"}})
+    );
+    let created = json_stdout(&out)["role"]["password"].clone();
+    assert!(created.is_string());
+
+    let out = fake.run(&[&args[..], &["role-reset-password"]].concat());
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "POST", &format!("{roles}/svc/reset_password"));
+    let reset = json_stdout(&out)["role"]["password"].clone();
+    assert_ne!(reset, created);
+
+    // The table leaves out most fields, but not the password.
+    let out = fake.run(&[&args[..], &["role-reset-password", "-f", "table"]].concat());
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert!(stdout(&out).contains("password"));
+    let table = stdout(&out);
+    let out = fake.run(&[&args[..], &["role-reveal-password"]].concat());
+    let reset = json_stdout(&out)["password"].clone();
+    assert!(table.contains(reset.as_str().unwrap()));
+
+    let out = fake.run(
+        &[
+            &args[..],
+            &["role-reveal-password", "-f", "raw", "--query", "password"],
+        ]
+        .concat(),
+    );
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "GET", &format!("{roles}/svc/reveal_password"));
+    assert_eq!(stdout(&out), format!("{}\n", reset.as_str().unwrap()));
+}
+
+#[test]
+fn branch_connection_uri_combines_role_database_and_endpoint() {
+    let fake = FakeNeon::start();
+    let args = [
+        "branch",
+        "-a",
+        "connection-uri",
+        "-p",
+        PROJECT,
+        "-b",
+        BRANCH,
+        "-r",
+        "alice",
+    ];
+    let out = fake.run(&[&["--database", "neondb"][..], &args[..]].concat());
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(
+        json_stdout(&out)["connection_uri"],
+        "postgres://alice:pw@ep-main.us-east-2.aws.neon.tech/neondb"
+    );
+
+    let pooled = ["--pooled", "-f", "raw", "--query", "connection_uri"];
+    let out = fake.run(&[&["--database", "neondb"][..], &args[..], &pooled[..]].concat());
+    assert_eq!(
+        stdout(&out),
+        "postgres://alice:pw@ep-main-pooler.us-east-2.aws.neon.tech/neondb\n"
+    );
+
+    assert_request(
+        &fake,
+        "GET",
+        &format!(
+            "/projects/{PROJECT}/connection_uri?branch_id={BRANCH}&database_name=neondb\
+             &role_name=alice&pooled=true"
+        ),
+    );
+
+    for body in [json!({}), json!({"uri": null})] {
+        fake.respond_next(200, body);
+        let out = fake.run(&[&["--database", "neondb"][..], &args[..]].concat());
+        assert!(!out.status.success());
+        assert!(
+            stderr(&out).contains("Unexpected response from the Neon API"),
+            "{}",
+            stderr(&out)
+        );
+    }
+
+    let out = fake.run(&[&args[..], &["-e", "ep-missing"]].concat());
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("Not found (404)"), "{}", stderr(&out));
+}
+
+#[test]
+fn branch_database_details_uses_session_database() {
+    let fake = FakeNeon::start();
+    let out = fake.run(&[
+        "--database",
+        "neondb",
+        "branch",
+        "-a",
+        "database-details",
+        "-p",
+        PROJECT,
+        "-b",
+        BRANCH,
+    ]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(
+        &fake,
+        "GET",
+        &format!("/projects/{PROJECT}/branches/{BRANCH}/databases/neondb"),
+    );
+    assert_eq!(json_stdout(&out)["database"]["owner_name"], "alice");
+}
+
+#[test]
+fn branch_database_create_update_and_delete() {
+    let fake = FakeNeon::start();
+    let databases = format!("/projects/{PROJECT}/branches/{BRANCH}/databases");
+    let args = ["branch", "-p", PROJECT, "-b", BRANCH, "-a"];
+
+    let create = [
+        "create-database",
+        "--database",
+        "analytics",
+        "--owner",
+        "alice",
+    ];
+    let out = fake.run(&[&args[..], &create[..]].concat());
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "POST", &databases);
+    assert_eq!(
+        fake.last_request().body.unwrap(),
+        json!({"database": {"name": "analytics", "owner_name": "alice"}})
+    );
+    assert_eq!(json_stdout(&out)["database"]["name"], "analytics");
+
+    let update = [
+        "update-database",
+        "--database",
+        "analytics",
+        "--name",
+        "reports",
+    ];
+    let out = fake.run(&[&args[..], &update[..]].concat());
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "PATCH", &format!("{databases}/analytics"));
+    assert_eq!(
+        fake.last_request().body.unwrap(),
+        json!({"database": {"name": "reports"}})
+    );
+
+    let out = fake.run(&[&args[..], &["delete-database", "--database", "reports"]].concat());
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "DELETE", &format!("{databases}/reports"));
+    let names: Vec<Value> = fake
+        .state
+        .lock()
+        .unwrap()
+        .databases
+        .iter()
+        .map(|d| d["name"].clone())
+        .collect();
+    assert_eq!(names, [json!("neondb")]);
+}
+
+#[test]
+fn branch_role_actions_need_a_role() {
+    let fake = FakeNeon::start();
+    let args = ["branch", "-p", PROJECT, "-b", BRANCH, "-a"];
+    for action in [
+        "role-details",
+        "role-create",
+        "role-reset-password",
+        "connection-uri",
+    ] {
+        let out = fake.run(&[&args[..], &[action]].concat());
+        assert!(!out.status.success());
+        let err = stderr(&out);
+        assert!(
+            err.contains(&format!("{action} needs the role to be named with -r")),
+            "{err}"
+        );
+        assert!(!err.contains("panicked"), "{err}");
+    }
+    assert!(fake.requests().is_empty());
+}
+
+#[test]
+fn branch_database_changes_need_an_explicit_database() {
+    let fake = FakeNeon::start();
+    let args = ["branch", "-p", PROJECT, "-b", BRANCH, "-a"];
+    // PGDATABASE names the database to connect to, not one to delete.
+    let out = common::cli()
+        .env("PGDATABASE", "neondb")
+        .args(["--api-url", &fake.url(), "--api-key", API_KEY])
+        .args([&args[..], &["delete-database"]].concat())
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("delete-database needs the database to be named with --database"));
+
+    let out = fake.run(&[&args[..], &["create-database", "--database", "x"]].concat());
+    assert!(stderr(&out).contains("--owner"), "{}", stderr(&out));
+    let out = fake.run(&[&args[..], &["update-database", "--database", "neondb"]].concat());
+    assert!(
+        stderr(&out).contains("a new --name or --owner"),
+        "{}",
+        stderr(&out)
+    );
+    assert!(fake.requests().is_empty());
+}
+
+#[test]
+fn branch_delete() {
+    let fake = FakeNeon::start();
+    let out = fake.run(&["branch", "-a", "delete-branch", "-p", PROJECT, "-b", BRANCH]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(
+        &fake,
+        "DELETE",
+        &format!("/projects/{PROJECT}/branches/{BRANCH}"),
+    );
+    assert_eq!(json_stdout(&out)["branch"]["id"], BRANCH);
+    assert!(fake.state.lock().unwrap().branches.is_empty());
+}
+
+#[test]
+fn branch_create_with_parent_and_endpoint() {
+    let fake = FakeNeon::start();
+    let out = fake.run(&[
+        "branch",
+        "-a",
+        "create-branch",
+        "-p",
+        PROJECT,
+        "--parent",
+        BRANCH,
+        "--name",
+        "dev",
+        "--parent-lsn",
+        "0/1FA5A60",
+        "--with-endpoint",
+    ]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "POST", &format!("/projects/{PROJECT}/branches"));
+    assert_eq!(
+        fake.last_request().body,
+        Some(json!({
+            "branch": {"parent_id": BRANCH, "name": "dev", "parent_lsn": "0/1FA5A60"},
+            "endpoints": [{"type": "read_write"}],
+        }))
+    );
+    let created = json_stdout(&out);
+    assert_eq!(created["branch"]["name"], "dev");
+    assert_eq!(created["endpoints"][0]["type"], "read_write");
+    assert!(created["connection_uris"][0]["connection_uri"]
+        .as_str()
+        .unwrap()
+        .starts_with("postgres://"));
+}
+
+#[test]
+fn branch_create_as_table_prints_connection_uri() {
+    let fake = FakeNeon::start();
+    let out = fake.run(&[
+        "branch",
+        "-a",
+        "create-branch",
+        "-p",
+        PROJECT,
+        "--parent-timestamp",
+        "2023-05-17T18:00:00Z",
+        "--with-endpoint",
+        "-f",
+        "table",
+    ]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(
+        fake.last_request().body.unwrap()["branch"],
+        json!({"parent_timestamp": "2023-05-17T18:00:00Z"})
+    );
+    let text = stdout(&out);
+    assert!(text.contains("read_write"), "{text}");
+    assert!(
+        text.contains("Connection URI: postgres://alice:pw@"),
+        "{text}"
+    );
+
+    // --columns picks the branch's columns, and --query replaces the whole table.
+    let create = [
+        "branch",
+        "-a",
+        "create-branch",
+        "-p",
+        PROJECT,
+        "-f",
+        "table",
+    ];
+    let out = fake.run(&[&create[..], &["--columns", "id"]].concat());
+    assert!(!stdout(&out).contains("parent_id"), "{}", stdout(&out));
+    let out = fake.run(&[&create[..], &["--with-endpoint", "--query", "branch.id"]].concat());
+    let text = stdout(&out);
+    assert!(
+        !text.contains("read_write") && !text.contains("Connection URI"),
+        "{text}"
+    );
+}
+
+#[test]
+fn branch_create_rejects_bad_point_in_time_options() {
+    let fake = FakeNeon::start();
+    let create = ["branch", "-a", "create-branch", "-p", PROJECT];
+
+    let out = fake.run(&[&create[..], &["--parent-timestamp", "yesterday"]].concat());
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("RFC 3339"), "{}", stderr(&out));
+
+    let both = [
+        "--parent-timestamp",
+        "2023-05-17T18:00:00Z",
+        "--parent-lsn",
+        "0/1",
+    ];
+    let out = fake.run(&[&create[..], &both[..]].concat());
+    assert!(!out.status.success());
+    assert!(fake.requests().is_empty());
+}
+
+#[test]
+fn endpoints_create_sends_initconfig_with_branch() {
+    let fake = FakeNeon::start();
+    let out = fake.run(&[
+        "endpoints",
+        "-a",
+        "create",
+        "-p",
+        PROJECT,
+        "-b",
+        BRANCH,
+        "--initconfig",
+        r#"{"type": "read_only", "autoscaling_limit_max_cu": 2}"#,
+    ]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "POST", &format!("/projects/{PROJECT}/endpoints"));
+    assert_eq!(
+        fake.last_request().body,
+        Some(json!({"endpoint": {
+            "type": "read_only",
+            "autoscaling_limit_max_cu": 2,
+            "branch_id": BRANCH,
+        }}))
+    );
+    let created = json_stdout(&out);
+    assert_eq!(created["endpoint"]["type"], "read_only");
+    assert_eq!(created["operations"][0]["action"], "start_compute");
+}
+
+#[test]
+fn endpoints_create_only_overrides_the_initconfig_branch_with_b() {
+    let fake = FakeNeon::start();
+    let initconfig = r#"{"type": "read_only", "branch_id": "br-from-config"}"#;
+    let create = ["endpoints", "-a", "create", "-p", PROJECT];
+    let out = common::cli()
+        .env("NEON_BRANCH_ID", "br-from-session")
+        .args(["--api-url", &fake.url(), "--api-key", API_KEY])
+        .args(create)
+        .args(["--initconfig", initconfig])
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(
+        fake.last_request().body.unwrap()["endpoint"]["branch_id"],
+        "br-from-config"
+    );
+
+    let out = fake.run(&[&create[..], &["-b", BRANCH, "--initconfig", initconfig]].concat());
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(
+        fake.last_request().body.unwrap()["endpoint"]["branch_id"],
+        BRANCH
+    );
+}
+
+#[test]
+fn endpoints_create_merges_typed_options_over_initconfig() {
+    let fake = FakeNeon::start();
+    let file = empty_dir().join("endpoint.json");
+    std::fs::write(
+        &file,
+        r#"{"type": "read_only", "pooler_mode": "session", "provisioner": "k8s-neonvm"}"#,
+    )
+    .unwrap();
+    let initconfig = format!("@{}", file.display());
+    let out = fake.run(&[
+        "endpoints",
+        "-a",
+        "create",
+        "-p",
+        PROJECT,
+        "-b",
+        BRANCH,
+        "--initconfig",
+        &initconfig,
+        "--type",
+        "read_write",
+        "--region",
+        "aws-us-east-2",
+        "--min-cu",
+        "0.25",
+        "--max-cu",
+        "2",
+        "--suspend-timeout",
+        "300",
+    ]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(
+        fake.last_request().body,
+        Some(json!({"endpoint": {
+            "type": "read_write", "region_id": "aws-us-east-2", "pooler_mode": "session",
+            "provisioner": "k8s-neonvm", "autoscaling_limit_min_cu": 0.25,
+            "autoscaling_limit_max_cu": 2.0, "suspend_timeout_seconds": 300,
+            "branch_id": BRANCH,
+        }}))
+    );
+}
+
+#[test]
+fn endpoints_create_checks_options_before_sending() {
+    let fake = FakeNeon::start();
+    let create = ["endpoints", "-a", "create", "-p", PROJECT, "-b", BRANCH];
+    for (options, message) in [
+        (&["--initconfig", "{not json"][..], "invalid JSON"),
+        (&["--initconfig", "[1]"][..], "expected a JSON object"),
+        (
+            &["--initconfig", "@/no/such/file.json"][..],
+            "can't read /no/such/file.json",
+        ),
+        (&["--type", "primary"][..], "invalid value 'primary'"),
+        (&["--max-cu", "3.5"][..], "whole number of compute units"),
+        (&[][..], "create needs --type read_write or read_only"),
+        (
+            &["--initconfig", r#"{"type": "replica"}"#][..],
+            "not replica",
+        ),
+        (
+            &[
+                "--initconfig",
+                r#"{"type": "read_write", "autoscaling_limit_min_cu": 8}"#,
+                "--max-cu",
+                "1",
+            ][..],
+            "--min-cu (8) can't be more than --max-cu (1)",
+        ),
+    ] {
+        let out = fake.run(&[&create[..], options].concat());
+        assert!(!out.status.success());
+        let err = stderr(&out);
+        assert!(err.contains(message), "{err}");
+        assert!(!err.contains("panicked"), "{err}");
+    }
+    assert!(fake.requests().is_empty());
+
+    let update = [
+        "endpoints",
+        "-a",
+        "update",
+        "-p",
+        PROJECT,
+        "-e",
+        ENDPOINT,
+        "--type",
+        "read_only",
+    ];
+    let out = fake.run(&update);
+    assert!(
+        stderr(&out).contains("--type can only be given to create"),
+        "{}",
+        stderr(&out)
+    );
+
+    for action in ["start", "suspend", "restart", "update"] {
+        let out = fake.run(&["endpoints", "-a", action, "-p", PROJECT, "--max-cu", "2"]);
+        assert!(!out.status.success());
+        let err = stderr(&out);
+        assert!(
+            err.contains(&format!("{action} needs the endpoint, given with -e")),
+            "{err}"
+        );
+        assert!(!err.contains("panicked"), "{err}");
+    }
+    assert!(fake.requests().is_empty());
+}
+
+#[test]
+fn endpoints_list_details_start_suspend_and_delete() {
+    let fake = FakeNeon::start();
+    let base = format!("/projects/{PROJECT}/endpoints");
+
+    let out = fake.run(&["endpoints", "-a", "list", "-p", PROJECT]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "GET", &base);
+    assert_eq!(json_stdout(&out)["endpoints"][0]["id"], ENDPOINT);
+
+    let out = fake.run(&["endpoints", "-a", "details", "-p", PROJECT, "-e", ENDPOINT]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "GET", &format!("{base}/{ENDPOINT}"));
+
+    let out = fake.run(&["endpoints", "-a", "start", "-p", PROJECT, "-e", ENDPOINT]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "POST", &format!("{base}/{ENDPOINT}/start"));
+    assert_eq!(json_stdout(&out)["endpoint"]["current_state"], "active");
+
+    let out = fake.run(&["endpoints", "-a", "suspend", "-p", PROJECT, "-e", ENDPOINT]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "POST", &format!("{base}/{ENDPOINT}/suspend"));
+    assert_eq!(json_stdout(&out)["endpoint"]["current_state"], "idle");
+
+    let out = fake.run(&["endpoints", "-a", "delete", "-p", PROJECT, "-e", ENDPOINT]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "DELETE", &format!("{base}/{ENDPOINT}"));
+    assert!(fake.state.lock().unwrap().endpoints.is_empty());
+}
+
+#[test]
+fn endpoints_update_sends_typed_settings() {
+    let fake = FakeNeon::start();
+    let update = ["endpoints", "-a", "update", "-p", PROJECT, "-e", ENDPOINT];
+    let settings = [
+        "--min-cu",
+        "0.5",
+        "--max-cu",
+        "4",
+        "--suspend-timeout",
+        "-1",
+        "--pooler-enabled",
+        "true",
+        "--pooler-mode",
+        "session",
+        "--passwordless-access",
+        "false",
+    ];
+    let out = fake.run(&[&update[..], &settings[..]].concat());
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(
+        &fake,
+        "PATCH",
+        &format!("/projects/{PROJECT}/endpoints/{ENDPOINT}"),
+    );
+    assert_eq!(
+        fake.last_request().body.unwrap(),
+        json!({"endpoint": {
+            "autoscaling_limit_min_cu": 0.5, "autoscaling_limit_max_cu": 4.0,
+            "suspend_timeout_seconds": -1, "pooler_enabled": true, "pooler_mode": "session",
+            "passwordless_access": false,
+        }})
+    );
+    assert_eq!(json_stdout(&out)["endpoint"]["pooler_mode"], "session");
+
+    for (options, message) in [
+        (&[][..], "at least one setting"),
+        (
+            &["--min-cu", "4", "--max-cu", "2"][..],
+            "can't be more than --max-cu",
+        ),
+        (
+            &["--pooler-mode", "statement"][..],
+            "invalid value 'statement'",
+        ),
+        (&["--suspend-timeout", "-2"][..], "invalid value '-2'"),
+    ] {
+        let requests = fake.requests().len();
+        let out = fake.run(&[&update[..], options].concat());
+        assert!(!out.status.success());
+        assert!(stderr(&out).contains(message), "{}", stderr(&out));
+        assert_eq!(fake.requests().len(), requests);
+    }
+}
+
+#[test]
+fn endpoints_restart_suspends_then_starts_waiting_for_each() {
+    let fake = FakeNeon::start();
+    fake.operations_report(&["running", "finished"]);
+    let out = fake.run(&["endpoints", "-a", "restart", "-p", PROJECT, "-e", ENDPOINT]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    let progress = stderr(&out);
+    let suspended = progress
+        .find("(suspend_compute): finished")
+        .expect(&progress);
+    let started = progress.find("(start_compute): running").expect(&progress);
+    assert!(suspended < started, "{progress}");
+    assert!(progress.contains("(start_compute): finished"), "{progress}");
+    assert_eq!(json_stdout(&out)["endpoint"]["current_state"], "active");
+}
+
+#[test]
+fn operations_list_and_details() {
+    let fake = FakeNeon::start();
+
+    let out = fake.run(&["operations", "-a", "list-operations", "-p", PROJECT]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "GET", &format!("/projects/{PROJECT}/operations"));
+    assert_eq!(json_stdout(&out)["operations"][0]["id"], "op-1");
+
+    let out = fake.run(&[
+        "operations",
+        "-a",
+        "operation-details",
+        "-p",
+        PROJECT,
+        "-o",
+        "op-1",
+    ]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(
+        &fake,
+        "GET",
+        &format!("/projects/{PROJECT}/operations/op-1"),
+    );
+    assert_eq!(json_stdout(&out)["operation"]["status"], "finished");
+}
+
+#[test]
+fn consumption_passes_cursor_and_limit() {
+    let fake = FakeNeon::start();
+    let out = fake.run(&["consumption", "-l", "5", "-c", "abc"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_request(&fake, "GET", "/consumption/projects?cursor=abc&limit=5");
+    assert_eq!(json_stdout(&out)["projects"][0]["project_id"], PROJECT);
+}
+
+#[test]
+fn row_formats_print_each_responses_items() {
+    let fake = FakeNeon::start();
+    let branch = ["-p", PROJECT, "-b", BRANCH];
+    let cases: Vec<(Vec<&str>, &str, &str)> = vec![
+        (vec!["projects", "-a", "list-projects"], "id", PROJECT),
+        (
+            vec!["projects", "-a", "project-details", "-p", PROJECT],
+            "id",
+            PROJECT,
+        ),
+        (vec!["keys", "-a", "list"], "name", "ci"),
+        (
+            [&["branch", "-a", "list-branches"], &branch[..]].concat(),
+            "id",
+            BRANCH,
+        ),
+        (
+            [&["branch", "-a", "branch-details"], &branch[..]].concat(),
+            "id",
+            BRANCH,
+        ),
+        (
+            [&["branch", "-a", "list-roles"], &branch[..]].concat(),
+            "name",
+            "alice",
+        ),
+        (
+            [
+                &["branch", "-a", "role-details", "-r", "alice"],
+                &branch[..],
+            ]
+            .concat(),
+            "name",
+            "alice",
+        ),
+        (
+            vec!["endpoints", "-a", "list", "-p", PROJECT],
+            "id",
+            ENDPOINT,
+        ),
+        (
+            vec!["endpoints", "-a", "details", "-p", PROJECT, "-e", ENDPOINT],
+            "id",
+            ENDPOINT,
+        ),
+        (
+            vec![
+                "operations",
+                "-a",
+                "operation-details",
+                "-p",
+                PROJECT,
+                "-o",
+                "op-1",
+            ],
+            "id",
+            "op-1",
+        ),
+        (vec!["consumption"], "project_id", PROJECT),
+    ];
+    for (args, field, value) in cases {
+        let out = fake.run(&[&args[..], &["-f", "ndjson"]].concat());
+        assert!(out.status.success(), "{args:?}: {}", stderr(&out));
+        let first = stdout(&out).lines().next().unwrap_or_default().to_string();
+        let first: serde_json::Value = serde_json::from_str(&first).unwrap();
+        assert_eq!(first[field], value, "{args:?}");
+    }
+}
+
+#[test]
+fn row_formats_select_sort_and_filter_items() {
+    let fake = FakeNeon::start();
+    for name in ["dev", "staging"] {
+        let out = fake.run(&[
+            "branch",
+            "-a",
+            "create-branch",
+            "-p",
+            PROJECT,
+            "--name",
+            name,
+        ]);
+        assert!(out.status.success(), "{}", stderr(&out));
+    }
+    let list = ["branch", "-a", "list-branches", "-p", PROJECT, "-f", "csv"];
+    let out = fake.run(&[&list[..], &["--columns", "name,primary"]].concat());
+    assert_eq!(
+        stdout(&out),
+        "name,primary\nmain,true\ndev,false\nstaging,false\n"
+    );
+
+    let options = [
+        "--columns",
+        "name",
+        "--sort-by",
+        "name:desc",
+        "--filter",
+        "primary=false",
+    ];
+    let out = fake.run(&[&list[..], &options[..]].concat());
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), "name\nstaging\ndev\n");
+
+    let out = fake.run(&[&list[..], &["--filter", "primary"]].concat());
+    assert!(!out.status.success());
+}
+
+#[test]
+fn query_picks_values_out_of_responses() {
+    let fake = FakeNeon::start();
+    let create = [
+        "branch",
+        "-a",
+        "create-branch",
+        "-p",
+        PROJECT,
+        "--name",
+        "dev",
+    ];
+    let out = fake.run(&[&create[..], &["--query", "branch.name", "-f", "raw"]].concat());
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), "dev\n");
+
+    let list = ["branch", "-a", "list-branches", "-p", PROJECT];
+    let out = fake.run(&[&list[..], &["--query", "[.branches[] | .name]"]].concat());
+    assert_eq!(json_stdout(&out), json!(["main", "dev"]));
+
+    let out = fake.run(&[&list[..], &["--query", "branches[*].name", "-f", "raw"]].concat());
+    assert_eq!(stdout(&out), "main\ndev\n");
+
+    // A query that doesn't parse is rejected before anything is sent.
+    let requests = fake.requests().len();
+    let out = fake.run(&[&create[..], &["--query", ".branch."]].concat());
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("invalid query"), "{}", stderr(&out));
+    assert_eq!(fake.requests().len(), requests);
+}
+
+#[test]
+fn row_formats_flatten_nested_objects() {
+    let fake = FakeNeon::start();
+    let list = ["projects", "-a", "list-projects", "-f", "csv"];
+    let out = fake.run(&[&list[..], &["--columns", "id,settings"]].concat());
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(
+        stdout(&out),
+        format!("id,settings.quota.active_time_seconds\n{PROJECT},100\n")
+    );
+
+    let out = fake.run(&[&list[..], &["--columns", "id,settings", "--max-depth", "1"]].concat());
+    assert_eq!(
+        stdout(&out),
+        format!("id,settings.quota\n{PROJECT},\"{{\"\"active_time_seconds\"\":100}}\"\n")
+    );
+
+    // ndjson keeps the nesting.
+    let list = [
+        "projects",
+        "-a",
+        "list-projects",
+        "-f",
+        "ndjson",
+        "--columns",
+        "settings",
+    ];
+    let out = fake.run(&list);
+    assert_eq!(
+        stdout(&out),
+        "{\"settings\":{\"quota\":{\"active_time_seconds\":100}}}\n"
+    );
+}
+
+/// Adds projects proj-2 up to proj-`n` after the seeded one.
+fn seed_projects(fake: &FakeNeon, n: usize) {
+    let mut state = fake.state.lock().unwrap();
+    for i in 2..=n {
+        state
+            .projects
+            .push(json!({"id": format!("proj-{i}"), "name": format!("p{i}")}));
+    }
+}
+
+#[test]
+fn all_follows_the_pagination_cursor() {
+    let fake = FakeNeon::start();
+    seed_projects(&fake, 5);
+    let list = ["projects", "-a", "list-projects", "--page-size", "2"];
+
+    let out = fake.run(&list);
+    assert_eq!(json_stdout(&out)["projects"].as_array().unwrap().len(), 2);
+
+    let out = fake.run(&[&list[..], &["--all"]].concat());
+    assert!(out.status.success(), "{}", stderr(&out));
+    let body = json_stdout(&out);
+    let ids: Vec<&str> = body["projects"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(ids, ["proj-1", "proj-2", "proj-3", "proj-4", "proj-5"]);
+    assert_eq!(body["pagination"]["cursor"], "proj-5");
+    let paths: Vec<String> = fake.requests().iter().map(|r| r.path.clone()).collect();
+    assert_eq!(
+        paths[1..],
+        [
+            "/projects?limit=2",
+            "/projects?cursor=proj-2&limit=2",
+            "/projects?cursor=proj-4&limit=2",
+        ]
+    );
+}
+
+#[test]
+fn all_streams_ndjson_and_stops_at_max_items() {
+    let fake = FakeNeon::start();
+    seed_projects(&fake, 5);
+    let list = ["projects", "-a", "list-projects", "--all", "-f", "ndjson"];
+    let options = ["--page-size", "2", "--max-items", "3", "--columns", "id"];
+    let out = fake.run(&[&list[..], &options[..]].concat());
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(
+        stdout(&out),
+        "{\"id\":\"proj-1\"}\n{\"id\":\"proj-2\"}\n{\"id\":\"proj-3\"}\n"
+    );
+    // The last page only asks for what is still wanted.
+    assert_eq!(fake.last_request().path, "/projects?cursor=proj-2&limit=1");
+
+    let out = fake.run(&["consumption", "--limit", "5", "--cursor", "proj-1"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(
+        fake.last_request().path,
+        "/consumption/projects?cursor=proj-1&limit=5"
+    );
+}
+
+#[test]
+fn yaml_prints_the_whole_response() {
+    let fake = FakeNeon::start();
+    let out = fake.run(&["endpoints", "-a", "list", "-p", PROJECT, "-f", "yaml"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    let yaml = stdout(&out);
+    assert!(yaml.starts_with("endpoints:\n"), "{yaml}");
+    assert!(yaml.contains(&format!("id: {ENDPOINT}")), "{yaml}");
+
+    let out = fake.run(&["keys", "-a", "list", "-f", "xml"]);
+    assert!(!out.status.success());
+}
+
+#[test]
+fn api_errors_exit_non_zero_with_the_api_message() {
+    let fake = FakeNeon::start();
+
+    fake.fail_next(401, "", "invalid API key");
+    let out = fake.run(&["projects", "-a", "list-projects"]);
+    assert!(!out.status.success());
+    assert!(stdout(&out).is_empty());
+    assert!(stderr(&out).contains("invalid API key"), "{}", stderr(&out));
+
+    let out = fake.run(&["projects", "-a", "project-details", "-p", "nope"]);
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("Not found (404)"), "{}", stderr(&out));
+}
+
+#[test]
+fn locked_reads_are_retried() {
+    let fake = FakeNeon::start();
+    fake.fail_next(423, "", "project already has running operations");
+    let out = fake.run(&["projects", "-a", "list-projects"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert!(stderr(&out).contains("Retrying"), "{}", stderr(&out));
+    assert_eq!(fake.requests().len(), 2);
+}
+
+#[test]
+fn writes_are_not_retried_unless_asked() {
+    let fake = FakeNeon::start();
+    fake.fail_next(423, "", "project already has running operations");
+    let start = ["endpoints", "-a", "start", "-p", PROJECT, "-e", ENDPOINT];
+    let out = fake.run(&start);
+    assert!(!out.status.success());
+    assert_eq!(fake.requests().len(), 1);
+
+    fake.fail_next(423, "", "project already has running operations");
+    let out = fake.run(&[&["--retry-writes"], &start[..]].concat());
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(fake.requests().len(), 3);
+}
+
+#[test]
+fn wait_polls_operations_until_they_finish() {
+    let fake = FakeNeon::start();
+    fake.operations_report(&["scheduling", "running", "finished"]);
+    let start = ["endpoints", "-a", "start", "-p", PROJECT, "-e", ENDPOINT];
+
+    let out = fake.run(&start);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(fake.requests().len(), 1);
+
+    let out = fake.run(&[&["--wait"], &start[..]].concat());
+    assert!(out.status.success(), "{}", stderr(&out));
+    let progress = stderr(&out);
+    assert!(progress.contains("(start_compute): running"), "{progress}");
+    assert!(progress.contains("(start_compute): finished"), "{progress}");
+    assert_eq!(json_stdout(&out)["endpoint"]["current_state"], "active");
+    let op = json_stdout(&out)["operations"][0]["id"].clone();
+    assert_request(
+        &fake,
+        "GET",
+        &format!("/projects/{PROJECT}/operations/{}", op.as_str().unwrap()),
+    );
+}
+
+#[test]
+fn wait_covers_create_branch_and_project_changes() {
+    let fake = FakeNeon::start();
+    fake.operations_report(&["running", "finished"]);
+    for command in [
+        &["branch", "-a", "create-branch", "-p", PROJECT][..],
+        &[
+            "projects",
+            "-a",
+            "update-project",
+            "-p",
+            PROJECT,
+            "--name",
+            "x",
+        ][..],
+    ] {
+        let out = fake.run(&[&["--wait"], command].concat());
+        assert!(out.status.success(), "{}", stderr(&out));
+        assert!(stderr(&out).contains(": finished"), "{}", stderr(&out));
+    }
+}
+
+#[test]
+fn wait_exits_non_zero_when_an_operation_fails() {
+    let fake = FakeNeon::start();
+    fake.operations_report(&["running", "failed"]);
+    let out = fake.run(&[
+        "--wait",
+        "branch",
+        "-a",
+        "create-branch",
+        "-p",
+        PROJECT,
+        "--with-endpoint",
+    ]);
+    assert!(!out.status.success());
+    assert!(stdout(&out).is_empty());
+    let err = stderr(&out);
+    assert!(
+        err.contains("(create_branch) failed: compute failed to start"),
+        "{err}"
+    );
+}
+
+#[test]
+fn wait_gives_up_after_the_timeout() {
+    let fake = FakeNeon::start();
+    fake.operations_report(&["running"]);
+    let out = fake.run(&[
+        "--wait",
+        "--timeout",
+        "1",
+        "endpoints",
+        "-a",
+        "suspend",
+        "-p",
+        PROJECT,
+        "-e",
+        ENDPOINT,
+    ]);
+    assert!(!out.status.success());
+    let err = stderr(&out);
+    assert!(err.contains("Timed out after 1s"), "{err}");
+    assert!(err.contains("still running"), "{err}");
+}
+
+#[test]
+fn database_commands_report_connection_failures() {
+    let unreachable = "postgres://nobody:pw@127.0.0.1:1/neondb";
+    for command in [&["query", "--sql", "select 1"][..], &["shell"][..]] {
+        let out = common::cli()
+            .args(["--connect-string", unreachable])
+            .args(command)
+            .output()
+            .unwrap();
+        assert_eq!(out.status.code(), Some(1));
+        assert!(
+            stderr(&out).contains("Error: error connecting to server"),
+            "{}",
+            stderr(&out)
+        );
+        assert!(!stderr(&out).contains("panicked"));
+    }
+}
@@ -0,0 +1,654 @@
+//! An in-process fake of the parts of the Neon v2 API the CLI talks to, plus helpers to
+//! run the compiled binary against it.
+#![allow(dead_code)]
+
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+pub const API_KEY: &str = "test-api-key";
+pub const PROJECT: &str = "proj-1";
+pub const BRANCH: &str = "br-main";
+pub const ENDPOINT: &str = "ep-main";
+
+/// A request as the fake server received it.
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    /// Path relative to the API root, including any query string.
+    pub path: String,
+    pub authorization: Option<String>,
+    pub body: Option<Value>,
+}
+
+/// The fake's in-memory data.  Tests can read or seed it through `FakeNeon::state`.
+#[derive(Debug, Default)]
+pub struct State {
+    pub projects: Vec<Value>,
+    pub branches: Vec<Value>,
+    pub endpoints: Vec<Value>,
+    pub roles: Vec<Value>,
+    pub databases: Vec<Value>,
+    pub operations: Vec<Value>,
+    pub keys: Vec<Value>,
+    pub requests: Vec<RecordedRequest>,
+    /// Canned responses returned, in order, before any real handling.
+    pub failures: VecDeque<(u16, Value)>,
+    /// Statuses a new operation reports, one per poll; it is created with the first.
+    /// When empty, operations are created "finished".
+    pub operation_statuses: Vec<&'static str>,
+    /// Each role's password, which Neon only shows when asked to reveal it.
+    pub passwords: HashMap<String, String>,
+    /// The statuses each unfinished operation has still to report.
+    pending: HashMap<String, VecDeque<&'static str>>,
+    next_id: u64,
+}
+
+impl State {
+    fn seeded() -> State {
+        let now = "2023-05-17T18:19:16Z";
+        State {
+            projects: vec![json!({
+                "id": PROJECT, "name": "test-project", "region_id": "aws-us-east-2",
+                "pg_version": 15, "created_at": now, "updated_at": now,
+                "settings": {"quota": {"active_time_seconds": 100}},
+            })],
+            branches: vec![json!({
+                "id": BRANCH, "project_id": PROJECT, "parent_id": null, "name": "main",
+                "current_state": "ready", "primary": true, "default": true,
+                "created_at": now, "updated_at": now,
+            })],
+            endpoints: vec![json!({
+                "id": ENDPOINT, "host": "ep-main.us-east-2.aws.neon.tech",
+                "project_id": PROJECT, "branch_id": BRANCH, "region_id": "aws-us-east-2",
+                "type": "read_write", "current_state": "idle",
+                "autoscaling_limit_min_cu": 0.25, "autoscaling_limit_max_cu": 1,
+                "pooler_enabled": false, "pooler_mode": "transaction", "disabled": false,
+                "passwordless_access": true, "suspend_timeout_seconds": 0,
+                "settings": {"pg_settings": {}}, "created_at": now, "updated_at": now,
+            })],
+            roles: vec![json!({
+                "branch_id": BRANCH, "name": "alice", "protected": false,
+                "created_at": now, "updated_at": now,
+            })],
+            databases: vec![json!({
+                "id": 1, "branch_id": BRANCH, "name": "neondb", "owner_name": "alice",
+                "created_at": now, "updated_at": now,
+            })],
+            operations: vec![json!({
+                "id": "op-1", "project_id": PROJECT, "branch_id": BRANCH, "action": "create_branch",
+                "status": "finished", "failures_count": 0, "created_at": now, "updated_at": now,
+            })],
+            keys: vec![json!({
+                "id": 1, "name": "ci", "created_at": now, "last_used_at": null,
+                "last_used_from_addr": "",
+            })],
+            passwords: HashMap::from([("alice".to_string(), "pw".to_string())]),
+            next_id: 100,
+            ..Default::default()
+        }
+    }
+
+    fn next_id(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+        format!("{prefix}-{}", self.next_id)
+    }
+
+    /// Records an operation for a mutating call, the way Neon returns them.
+    fn operation(&mut self, action: &str, branch: Option<&str>, endpoint: Option<&str>) -> Value {
+        let id = self.next_id("op");
+        let mut statuses: VecDeque<&str> = self.operation_statuses.iter().copied().collect();
+        let status = statuses.pop_front().unwrap_or("finished");
+        let op = json!({
+            "id": id, "project_id": PROJECT, "branch_id": branch,
+            "endpoint_id": endpoint, "action": action, "status": status,
+            "failures_count": 0, "created_at": "now", "updated_at": "now",
+        });
+        self.pending.insert(id, statuses);
+        self.operations.push(op.clone());
+        op
+    }
+
+    /// Moves an operation on to its next status, as if it made progress since the last poll.
+    fn poll_operation(&mut self, id: &str) -> Option<Value> {
+        let next = self.pending.get_mut(id).and_then(|s| s.pop_front());
+        let op = self.operations.iter_mut().find(|o| o["id"] == id)?;
+        if let Some(status) = next {
+            op["status"] = json!(status);
+            if status == "failed" {
+                op["error"] = json!("compute failed to start");
+            }
+        }
+        Some(op.clone())
+    }
+}
+
+/// Matches string ids (projects, branches) and numeric ids (keys, databases) alike.
+fn has(item: &Value, key: &str, value: &str) -> bool {
+    match &item[key] {
+        Value::String(s) => s == value,
+        other => other.as_i64().is_some() && other.as_i64() == value.parse().ok(),
+    }
+}
+
+fn find<'a>(items: &'a [Value], key: &str, value: &str) -> Option<&'a Value> {
+    items.iter().find(|v| has(v, key, value))
+}
+
+fn take(items: &mut Vec<Value>, key: &str, value: &str) -> Option<Value> {
+    let pos = items.iter().position(|v| has(v, key, value))?;
+    Some(items.remove(pos))
+}
+
+/// A parameter of a query string, as sent.
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .find_map(|p| p.strip_prefix(name)?.strip_prefix('='))
+}
+
+/// One page of `items` for a `?cursor=..&limit=..` query, the way Neon pages lists: the
+/// cursor is the id of the last item of the page before.  Without a limit, everything after
+/// the cursor is one page.
+fn page(items: &[Value], query: &str) -> (Vec<Value>, Value) {
+    let param = |name| query_param(query, name);
+    let start = match param("cursor") {
+        Some(cursor) => items
+            .iter()
+            .position(|v| has(v, "id", cursor))
+            .map_or(items.len(), |i| i + 1),
+        None => 0,
+    };
+    let limit = param("limit").map_or(usize::MAX, |l| l.parse().unwrap());
+    let page: Vec<Value> = items[start..].iter().take(limit).cloned().collect();
+    let cursor = page.last().map_or(json!(""), |v| v["id"].clone());
+    (page, json!({ "cursor": cursor }))
+}
+
+fn not_found() -> (u16, Value) {
+    (404, json!({"code": "", "message": "not found"}))
+}
+
+fn route(state: &mut State, method: &str, path: &str, body: &Option<Value>) -> (u16, Value) {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    let segs: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let found = |v: Option<Value>, wrap: &str| match v {
+        Some(v) => (200, json!({ wrap: v })),
+        None => not_found(),
+    };
+    match (method, segs.as_slice()) {
+        ("GET", ["api_keys"]) => (200, Value::Array(state.keys.clone())),
+        ("POST", ["api_keys"]) => {
+            let id = state.keys.len() as i64 + 1;
+            let name = body
+                .as_ref()
+                .map(|b| b["key_name"].clone())
+                .unwrap_or_default();
+            state
+                .keys
+                .push(json!({"id": id, "name": name, "created_at": "now",
+                "last_used_at": null, "last_used_from_addr": ""}));
+            (200, json!({"id": id, "key": "secret-key-value"}))
+        }
+        ("DELETE", ["api_keys", id]) => match take(&mut state.keys, "id", id) {
+            Some(mut k) => {
+                k["revoked"] = json!(true);
+                (200, k)
+            }
+            None => not_found(),
+        },
+        ("GET", ["consumption", "projects"]) => (
+            200,
+            json!({"projects": [{"project_id": PROJECT, "periods": []}],
+                   "pagination": {"cursor": PROJECT}}),
+        ),
+        ("GET", ["projects"]) => {
+            let (projects, pagination) = page(&state.projects, query);
+            (200, json!({"projects": projects, "pagination": pagination}))
+        }
+        ("POST", ["projects"]) => {
+            let body = body.clone().unwrap_or_default();
+            let mut project = body["project"].clone();
+            let id = state.next_id("proj");
+            let branch_name = project["branch"]["name"]
+                .as_str()
+                .unwrap_or("main")
+                .to_string();
+            if let Some(fields) = project.as_object_mut() {
+                fields.remove("branch");
+            }
+            project["id"] = json!(id);
+            for (key, default) in [
+                ("name", json!(id)),
+                ("region_id", json!("aws-us-east-2")),
+                ("pg_version", json!(16)),
+            ] {
+                if project[key].is_null() {
+                    project[key] = default;
+                }
+            }
+            state.projects.push(project.clone());
+            let branch = json!({
+                "id": state.next_id("br"), "name": branch_name, "primary": true, "default": true,
+            });
+            let endpoint = state.next_id("ep");
+            let host = format!("{endpoint}.us-east-2.aws.neon.tech");
+            let op = state.operation("create_timeline", None, None);
+            (
+                201,
+                json!({"project": project, "branch": branch, "operations": [op],
+                "endpoints": [{"id": endpoint, "host": host, "type": "read_write"}],
+                "roles": [{"name": "owner"}],
+                "databases": [{"id": 1, "name": "neondb", "owner_name": "owner"}],
+                "connection_uris": [{
+                    "connection_uri": format!("postgres://owner:pw@{host}/neondb"),
+                }]}),
+            )
+        }
+        ("PATCH", ["projects", p]) => {
+            let changes = body.clone().unwrap_or_default()["project"].clone();
+            let Some(project) = state.projects.iter_mut().find(|v| has(v, "id", p)) else {
+                return not_found();
+            };
+            for (key, value) in changes.as_object().cloned().unwrap_or_default() {
+                project[key] = value;
+            }
+            let project = project.clone();
+            let op = state.operation("apply_config", None, None);
+            (200, json!({"project": project, "operations": [op]}))
+        }
+        ("GET", ["projects", _, "connection_uri"]) => {
+            let endpoint = query_param(query, "endpoint_id").unwrap_or(ENDPOINT);
+            if find(&state.endpoints, "id", endpoint).is_none() {
+                return not_found();
+            }
+            let pooler = match query_param(query, "pooled") {
+                Some("true") => "-pooler",
+                _ => "",
+            };
+            let uri = format!(
+                "postgres://{}:pw@{endpoint}{pooler}.us-east-2.aws.neon.tech/{}",
+                query_param(query, "role_name").unwrap_or_default(),
+                query_param(query, "database_name").unwrap_or_default()
+            );
+            (200, json!({ "uri": uri }))
+        }
+        ("GET", ["projects", p]) => found(find(&state.projects, "id", p).cloned(), "project"),
+        ("DELETE", ["projects", p]) => found(take(&mut state.projects, "id", p), "project"),
+        ("GET", ["projects", _, "branches"]) => (200, json!({"branches": state.branches})),
+        ("POST", ["projects", p, "branches"]) => {
+            let body = body.clone().unwrap_or_default();
+            let mut branch = body["branch"].clone();
+            if branch.is_null() {
+                branch = json!({});
+            }
+            let id = state.next_id("br");
+            branch["id"] = json!(id);
+            branch["project_id"] = json!(p);
+            if branch["parent_id"].is_null() {
+                branch["parent_id"] = json!(BRANCH);
+            }
+            if branch["name"].is_null() {
+                branch["name"] = json!(id);
+            }
+            branch["current_state"] = json!("init");
+            branch["primary"] = json!(false);
+            branch["default"] = json!(false);
+            state.branches.push(branch.clone());
+            let mut operations = vec![state.operation("create_branch", Some(&id), None)];
+            let mut endpoints = vec![];
+            let mut connection_uris = vec![];
+            for requested in body["endpoints"].as_array().cloned().unwrap_or_default() {
+                let ep = state.next_id("ep");
+                let host = format!("{ep}.us-east-2.aws.neon.tech");
+                let endpoint = json!({
+                    "id": ep, "host": host, "project_id": p, "branch_id": id,
+                    "type": requested["type"], "current_state": "init",
+                });
+                state.endpoints.push(endpoint.clone());
+                endpoints.push(endpoint);
+                operations.push(state.operation("start_compute", Some(&id), Some(&ep)));
+                connection_uris.push(json!({
+                    "connection_uri": format!("postgres://alice:pw@{host}/neondb"),
+                    "connection_parameters": {"host": host, "role": "alice", "database": "neondb"},
+                }));
+            }
+            (
+                201,
+                json!({"branch": branch, "endpoints": endpoints, "operations": operations,
+                       "roles": state.roles, "databases": state.databases,
+                       "connection_uris": connection_uris}),
+            )
+        }
+        ("GET", ["projects", _, "branches", b]) => {
+            found(find(&state.branches, "id", b).cloned(), "branch")
+        }
+        ("DELETE", ["projects", _, "branches", b]) => match take(&mut state.branches, "id", b) {
+            Some(branch) => {
+                let op = state.operation("delete_timeline", Some(b), None);
+                (200, json!({"branch": branch, "operations": [op]}))
+            }
+            None => not_found(),
+        },
+        ("GET", ["projects", _, "branches", b, "endpoints"]) => {
+            let eps: Vec<Value> = state
+                .endpoints
+                .iter()
+                .filter(|e| e["branch_id"] == *b)
+                .cloned()
+                .collect();
+            (200, json!({ "endpoints": eps }))
+        }
+        ("GET", ["projects", _, "branches", _, "roles"]) => (200, json!({"roles": state.roles})),
+        ("POST", ["projects", _, "branches", b, "roles"]) => {
+            let name = body.as_ref().map(|b| b["role"]["name"].clone());
+            let name = name
+                .unwrap_or_default()
+                .as_str()
+                .unwrap_or_default()
+                .to_string();
+            let password = state.next_id("pw");
+            state.passwords.insert(name.clone(), password.clone());
+            let role = json!({"branch_id": b, "name": name, "protected": false});
+            state.roles.push(role.clone());
+            let mut shown = role;
+            shown["password"] = json!(password);
+            let op = state.operation("apply_config", Some(b), None);
+            (201, json!({"role": shown, "operations": [op]}))
+        }
+        ("POST", ["projects", _, "branches", b, "roles", r, "reset_password"]) => {
+            let Some(mut role) = find(&state.roles, "name", r).cloned() else {
+                return not_found();
+            };
+            let password = state.next_id("pw");
+            state.passwords.insert(r.to_string(), password.clone());
+            role["password"] = json!(password);
+            let op = state.operation("apply_config", Some(b), None);
+            (200, json!({"role": role, "operations": [op]}))
+        }
+        ("GET", ["projects", _, "branches", _, "roles", r, "reveal_password"]) => {
+            match state.passwords.get(*r) {
+                Some(password) => (200, json!({ "password": password })),
+                None => not_found(),
+            }
+        }
+        ("GET", ["projects", _, "branches", _, "roles", r]) => {
+            found(find(&state.roles, "name", r).cloned(), "role")
+        }
+        ("DELETE", ["projects", _, "branches", b, "roles", r]) => {
+            match take(&mut state.roles, "name", r) {
+                Some(role) => {
+                    let op = state.operation("apply_config", Some(b), None);
+                    (200, json!({"role": role, "operations": [op]}))
+                }
+                None => not_found(),
+            }
+        }
+        ("GET", ["projects", _, "branches", _, "databases"]) => {
+            (200, json!({"databases": state.databases}))
+        }
+        ("GET", ["projects", _, "branches", _, "databases", d]) => {
+            found(find(&state.databases, "name", d).cloned(), "database")
+        }
+        ("POST", ["projects", _, "branches", b, "databases"]) => {
+            let requested = body.clone().unwrap_or_default()["database"].clone();
+            let id = state.databases.len() + 1;
+            let database = json!({
+                "id": id, "branch_id": b, "name": requested["name"],
+                "owner_name": requested["owner_name"],
+            });
+            state.databases.push(database.clone());
+            let op = state.operation("apply_config", Some(b), None);
+            (201, json!({"database": database, "operations": [op]}))
+        }
+        ("PATCH", ["projects", _, "branches", b, "databases", d]) => {
+            let changes = body.clone().unwrap_or_default()["database"].clone();
+            let Some(database) = state.databases.iter_mut().find(|v| has(v, "name", d)) else {
+                return not_found();
+            };
+            for (key, value) in changes.as_object().cloned().unwrap_or_default() {
+                database[key] = value;
+            }
+            let database = database.clone();
+            let op = state.operation("apply_config", Some(b), None);
+            (200, json!({"database": database, "operations": [op]}))
+        }
+        ("DELETE", ["projects", _, "branches", b, "databases", d]) => {
+            match take(&mut state.databases, "name", d) {
+                Some(database) => {
+                    let op = state.operation("apply_config", Some(b), None);
+                    (200, json!({"database": database, "operations": [op]}))
+                }
+                None => not_found(),
+            }
+        }
+        ("GET", ["projects", _, "endpoints"]) => (200, json!({"endpoints": state.endpoints})),
+        ("POST", ["projects", p, "endpoints"]) => {
+            let mut endpoint = body
+                .as_ref()
+                .map(|b| b["endpoint"].clone())
+                .unwrap_or_default();
+            let id = state.next_id("ep");
+            endpoint["id"] = json!(id);
+            endpoint["project_id"] = json!(p);
+            endpoint["host"] = json!(format!("{id}.us-east-2.aws.neon.tech"));
+            endpoint["current_state"] = json!("init");
+            state.endpoints.push(endpoint.clone());
+            let branch = endpoint["branch_id"].as_str().map(|s| s.to_string());
+            let op = state.operation("start_compute", branch.as_deref(), Some(&id));
+            (201, json!({"endpoint": endpoint, "operations": [op]}))
+        }
+        ("GET", ["projects", _, "endpoints", e]) => {
+            found(find(&state.endpoints, "id", e).cloned(), "endpoint")
+        }
+        ("PATCH", ["projects", _, "endpoints", e]) => {
+            let changes = body.clone().unwrap_or_default()["endpoint"].clone();
+            let Some(endpoint) = state.endpoints.iter_mut().find(|v| has(v, "id", e)) else {
+                return not_found();
+            };
+            for (key, value) in changes.as_object().cloned().unwrap_or_default() {
+                endpoint[key] = value;
+            }
+            let endpoint = endpoint.clone();
+            let op = state.operation("apply_config", None, Some(e));
+            (200, json!({"endpoint": endpoint, "operations": [op]}))
+        }
+        ("DELETE", ["projects", _, "endpoints", e]) => match take(&mut state.endpoints, "id", e) {
+            Some(endpoint) => {
+                let op = state.operation("delete_compute", None, Some(e));
+                (200, json!({"endpoint": endpoint, "operations": [op]}))
+            }
+            None => not_found(),
+        },
+        ("POST", ["projects", _, "endpoints", e, action @ ("start" | "suspend")]) => {
+            let new_state = if *action == "start" { "active" } else { "idle" };
+            let endpoint = state.endpoints.iter_mut().find(|v| v["id"] == *e);
+            match endpoint {
+                Some(endpoint) => {
+                    endpoint["current_state"] = json!(new_state);
+                    let endpoint = endpoint.clone();
+                    let op_action = format!("{action}_compute");
+                    let op = state.operation(&op_action, None, Some(e));
+                    (200, json!({"endpoint": endpoint, "operations": [op]}))
+                }
+                None => not_found(),
+            }
+        }
+        ("GET", ["projects", _, "operations"]) => {
+            let (operations, pagination) = page(&state.operations, query);
+            (
+                200,
+                json!({"operations": operations, "pagination": pagination}),
+            )
+        }
+        ("GET", ["projects", _, "operations", o]) => found(state.poll_operation(o), "operation"),
+        _ => not_found(),
+    }
+}
+
+/// A fake Neon API listening on a random local port for the lifetime of the test.
+pub struct FakeNeon {
+    addr: String,
+    pub state: Arc<Mutex<State>>,
+}
+
+impl FakeNeon {
+    pub fn start() -> FakeNeon {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let state = Arc::new(Mutex::new(State::seeded()));
+        let shared = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = shared.clone();
+                thread::spawn(move || handle(stream, &state));
+            }
+        });
+        FakeNeon { addr, state }
+    }
+
+    /// What to pass as `--api-url`.
+    pub fn url(&self) -> String {
+        format!("http://{}/api/v2", self.addr)
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// The last request the server saw.
+    pub fn last_request(&self) -> RecordedRequest {
+        self.requests()
+            .last()
+            .cloned()
+            .expect("no requests were made")
+    }
+
+    /// Makes the next request fail with `status` and a Neon-style error body.
+    pub fn fail_next(&self, status: u16, code: &str, message: &str) {
+        self.respond_next(status, json!({"code": code, "message": message}));
+    }
+
+    /// Makes the next request get `body` with `status`, whatever it asks for.
+    pub fn respond_next(&self, status: u16, body: Value) {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .push_back((status, body));
+    }
+
+    /// Makes operations created from now on go through `statuses`, one per poll.
+    pub fn operations_report(&self, statuses: &[&'static str]) {
+        self.state.lock().unwrap().operation_statuses = statuses.to_vec();
+    }
+
+    /// Runs the CLI against this server and returns its output.
+    pub fn run(&self, args: &[&str]) -> Output {
+        let url = self.url();
+        let mut all = vec!["--api-url", url.as_str(), "--api-key", API_KEY];
+        all.extend(args);
+        cli().args(&all).output().expect("failed to run neon-cli")
+    }
+}
+
+/// A command for the compiled binary, isolated from the developer's own settings.
+pub fn cli() -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_neon-cli"));
+    for (key, _) in std::env::vars() {
+        if key.starts_with("NEON_") || key.starts_with("PG") || key == "DATABASE_URL" {
+            cmd.env_remove(key);
+        }
+    }
+    cmd.env("NEON_CLI_CONFIG", empty_dir().join("config.toml"));
+    cmd.current_dir(empty_dir());
+    cmd
+}
+
+/// A fresh directory with no .env or config file in it.
+pub fn empty_dir() -> PathBuf {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+    let dir = std::env::temp_dir().join(format!("neon-cli-test-{}-{n}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+/// Parses stdout as JSON, failing the test with both streams if it isn't.
+pub fn json_stdout(output: &Output) -> Value {
+    serde_json::from_str(&stdout(output)).unwrap_or_else(|e| {
+        panic!(
+            "stdout is not JSON ({e})\nstdout:\n{}\nstderr:\n{}",
+            stdout(output),
+            stderr(output)
+        )
+    })
+}
+
+fn handle(stream: TcpStream, state: &Mutex<State>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_string();
+    let target = parts.next().unwrap_or("").to_string();
+
+    let mut headers: HashMap<String, String> = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+            break;
+        }
+        if let Some((k, v)) = line.split_once(':') {
+            headers.insert(k.trim().to_lowercase(), v.trim().to_string());
+        }
+    }
+    let length: usize = headers
+        .get("content-length")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(0);
+    let mut raw = vec![0; length];
+    reader.read_exact(&mut raw).unwrap();
+    let body: Option<Value> = serde_json::from_slice(&raw).ok();
+
+    let path = target
+        .strip_prefix("/api/v2")
+        .unwrap_or(&target)
+        .to_string();
+    let (status, response) = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(RecordedRequest {
+            method: method.clone(),
+            path: path.clone(),
+            authorization: headers.get("authorization").cloned(),
+            body: body.clone(),
+        });
+        match state.failures.pop_front() {
+            Some(failure) => failure,
+            None => route(&mut state, &method, &path, &body),
+        }
+    };
+    let text = response.to_string();
+    let reply = format!(
+        "HTTP/1.1 {status} Fake\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{text}",
+        text.len()
+    );
+    let mut stream = stream;
+    let _ = stream.write_all(reply.as_bytes());
+}